@@ -1,7 +1,6 @@
-// This Lambda function shows how you might run async code in your handler, even though minlambda
-// lacks any first-class support for async code.
+// This Lambda function shows how you might run async code in your handler, using the Tokio
+// runtime that minlambda sets up when the `tokio` feature is enabled.
 
-use futures_util::future::TryFutureExt;
 use serde_derive::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -9,14 +8,16 @@ struct HandlerResponse {
     body: String,
 }
 
+async fn handler(
+    _: serde::de::IgnoredAny,
+) -> Result<HandlerResponse, Box<dyn std::error::Error + Send + Sync>> {
+    let body = reqwest::get("https://www.example.com/")
+        .await?
+        .text()
+        .await?;
+    Ok(HandlerResponse { body })
+}
+
 fn main() {
-    let mut runtime = tokio::runtime::Runtime::new().unwrap();
-    minlambda::run(|_: serde::de::IgnoredAny| {
-        runtime.block_on(async {
-            reqwest::get("https://www.example.com/")
-                .and_then(|response| response.text())
-                .await
-                .map(|body| HandlerResponse { body })
-        })
-    })
+    minlambda::run_async(handler)
 }
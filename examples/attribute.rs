@@ -0,0 +1,7 @@
+// This Lambda function uses the `#[minlambda::handler]` attribute (from the `macros` feature) to
+// generate its `main` function.
+
+#[minlambda::handler]
+fn handler(value: serde_json::Value) -> Result<serde_json::Value, std::convert::Infallible> {
+    Ok(value)
+}
@@ -0,0 +1,126 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Attribute macros for [minlambda](https://docs.rs/minlambda). Use them through minlambda's
+//! `macros` feature rather than depending on this crate directly.
+//!
+//! In the spirit of minlambda, this crate has no dependencies; it only looks at as much of the
+//! function as it needs to.
+
+#![forbid(unsafe_code)]
+#![deny(
+    future_incompatible,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unused
+)]
+#![warn(clippy::pedantic)]
+
+use proc_macro::{TokenStream, TokenTree};
+
+/// Generates a `main` function that calls `minlambda::run` with the annotated function.
+///
+/// ```rust,ignore
+/// #[minlambda::handler]
+/// fn handler(event: MyEvent) -> Result<MyResponse, MyError> {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("#[minlambda::handler] does not take any arguments");
+    }
+    match signature(&item) {
+        Some((_, true)) => compile_error("use #[minlambda::main] for async functions"),
+        Some((name, false)) => with_main(item, &format!("::minlambda::run({name})")),
+        None => compile_error("#[minlambda::handler] must be used on a function"),
+    }
+}
+
+/// Generates a `main` function that runs the annotated async function as a handler.
+///
+/// By default, the handler runs on a current-thread Tokio runtime if minlambda's `tokio` feature
+/// is enabled, and on minlambda's built-in executor otherwise. Any other
+/// `minlambda::Executor` can be chosen with the `executor` argument:
+///
+/// ```rust,ignore
+/// #[minlambda::main(executor = minlambda::executor::Smol)]
+/// async fn handler(event: MyEvent) -> Result<MyResponse, MyError> {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let run = match executor(attr) {
+        Ok(Some(executor)) => format!("::minlambda::run_async_on({executor}, "),
+        Ok(None) => String::from("::minlambda::__run_async_default("),
+        Err(message) => return compile_error(message),
+    };
+    match signature(&item) {
+        Some((name, true)) => with_main(item, &format!("{run}{name})")),
+        Some((_, false)) => compile_error("use #[minlambda::handler] for non-async functions"),
+        None => compile_error("#[minlambda::main] must be used on a function"),
+    }
+}
+
+/// Parses the `executor = <expr>` argument to `#[minlambda::main]`.
+fn executor(attr: TokenStream) -> Result<Option<String>, &'static str> {
+    const USAGE: &str = "expected `#[minlambda::main]` or `#[minlambda::main(executor = ...)]`";
+
+    let mut tokens = attr.into_iter();
+    match tokens.next() {
+        None => return Ok(None),
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "executor" => {}
+        Some(_) => return Err(USAGE),
+    }
+    match tokens.next() {
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => {}
+        _ => return Err(USAGE),
+    }
+    let executor = tokens.collect::<TokenStream>();
+    if executor.is_empty() {
+        Err(USAGE)
+    } else {
+        Ok(Some(executor.to_string()))
+    }
+}
+
+/// Returns the function's name and whether it is `async`.
+fn signature(item: &TokenStream) -> Option<(String, bool)> {
+    let mut is_async = false;
+    let mut tokens = item.clone().into_iter();
+    while let Some(token) = tokens.next() {
+        if let TokenTree::Ident(ident) = token {
+            match ident.to_string().as_str() {
+                "async" => is_async = true,
+                "fn" => {
+                    return match tokens.next() {
+                        Some(TokenTree::Ident(name)) => Some((name.to_string(), is_async)),
+                        _ => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+fn with_main(item: TokenStream, body: &str) -> TokenStream {
+    let mut output = item;
+    output.extend(parse(&format!("fn main() -> ! {{ {body} }}")));
+    output
+}
+
+fn compile_error(message: &str) -> TokenStream {
+    parse(&format!("::core::compile_error!({message:?});"))
+}
+
+fn parse(source: &str) -> TokenStream {
+    source
+        .parse()
+        .unwrap_or_else(|_| unreachable!("generated code is valid"))
+}
@@ -0,0 +1,58 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use std::sync::{Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+enum Task {
+    Deferred(Box<dyn FnOnce() + Send>),
+    Thread(JoinHandle<()>),
+}
+
+static TASKS: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+
+fn push(task: Task) {
+    TASKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(task);
+}
+
+/// Schedules work to run after the current invocation's response has been sent, but before the
+/// next invocation is requested.
+///
+/// Lambda freezes the execution environment once the runtime asks for the next invocation, so
+/// work left running past that point (such as flushing metrics or other fire-and-forget requests)
+/// may not happen until the next invocation, if ever. Deferred work runs on the thread that
+/// handles invocations, in the order it was scheduled.
+pub fn defer<F: FnOnce() + Send + 'static>(f: F) {
+    push(Task::Deferred(Box::new(f)));
+}
+
+/// Starts work on a new thread now, and waits for it to finish after the current invocation's
+/// response has been sent, but before the next invocation is requested.
+///
+/// This is like [`defer`], but the work runs concurrently with the rest of the handler. A panic on
+/// the background thread is not propagated.
+pub fn spawn_background<F: FnOnce() + Send + 'static>(f: F) {
+    push(Task::Thread(std::thread::spawn(f)));
+}
+
+/// Runs deferred work and joins background threads, including any scheduled along the way.
+pub(crate) fn finish() {
+    loop {
+        let tasks = std::mem::take(&mut *TASKS.lock().unwrap_or_else(PoisonError::into_inner));
+        if tasks.is_empty() {
+            return;
+        }
+        for task in tasks {
+            match task {
+                Task::Deferred(f) => f(),
+                Task::Thread(thread) => {
+                    // The panic has already been printed by the panic hook.
+                    let _ = thread.join();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Just enough base64 for the runtime API's encoded headers.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(char::from(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f]));
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Decodes standard or URL-safe base64. Padding is optional, but if it's there, it has to fill out
+/// the last group of four characters.
+pub(crate) fn decode(input: &str) -> Option<Vec<u8>> {
+    let data = input.trim_end_matches('=');
+    let padding = input.len() - data.len();
+    if data.len() % 4 == 1 || (padding > 0 && (padding > 2 || !input.len().is_multiple_of(4))) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0_u32;
+    let mut bits = 0;
+    for c in data.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        acc = ((acc << 6) | u32::from(value)) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((acc >> bits).to_le_bytes()[0]);
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+
+    #[test]
+    fn round_trip() {
+        for input in [
+            &b""[..],
+            b"f",
+            b"fo",
+            b"foo",
+            b"foob",
+            b"fooba",
+            b"foobar",
+            &[0xff; 7],
+        ] {
+            assert_eq!(decode(&encode(input)).as_deref(), Some(input));
+        }
+        assert_eq!(encode(b"foob"), "Zm9vYg==");
+        assert_eq!(encode(b"fooba"), "Zm9vYmE=");
+    }
+
+    #[test]
+    fn unpadded_and_url_safe() {
+        assert_eq!(decode("Zm9vYg").as_deref(), Some(&b"foob"[..]));
+        assert_eq!(decode("Zm9vYmE").as_deref(), Some(&b"fooba"[..]));
+        assert_eq!(decode("-_8").as_deref(), Some(&[0xfb, 0xff][..]));
+        assert_eq!(decode("+/8=").as_deref(), Some(&[0xfb, 0xff][..]));
+    }
+
+    #[test]
+    fn invalid_padding() {
+        for input in [
+            "Zm9vYg=",
+            "Zm9vYg===",
+            "Zm9vYmE==",
+            "Zm9v=",
+            "Zm9v====",
+            "=",
+            "==",
+            "Z===",
+            "Zm=9vYg==",
+            "Zm9vY",
+        ] {
+            assert_eq!(decode(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn invalid_characters() {
+        for input in ["Zm9v Yg==", "Zm9v\nYg==", "Zm9v*Yg=", "Zm9vYg==\0"] {
+            assert_eq!(decode(input), None, "{input:?}");
+        }
+    }
+}
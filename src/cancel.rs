@@ -0,0 +1,106 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long before the deadline a [`CancellationToken`] is cancelled.
+const MARGIN: Duration = Duration::from_millis(500);
+
+/// A flag that is set shortly before an invocation's deadline, passed to handlers by
+/// [`run_cancellable`].
+///
+/// Long-running handlers can check [`is_cancelled`](CancellationToken::is_cancelled) between units
+/// of work and return early (perhaps with a partial result) instead of being stopped by Lambda
+/// mid-task. Tokens are cheap to clone and can be sent to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    #[must_use]
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Returns whether the token has been cancelled.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Cancels the token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// [`run`](crate::run), for handlers that want to stop work before the function times out.
+///
+/// The handler receives a [`CancellationToken`] alongside each event, which is cancelled half a
+/// second before the invocation's deadline by a timer thread started by this function.
+///
+/// ```rust,no_run
+/// fn main() -> ! {
+///     minlambda::run_cancellable(|items: Vec<u64>, token: minlambda::CancellationToken| {
+///         let mut done = Vec::new();
+///         for item in items {
+///             if token.is_cancelled() {
+///                 break;
+///             }
+///             done.push(item * 2);
+///         }
+///         Ok::<_, std::convert::Infallible>(done)
+///     })
+/// }
+/// ```
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_cancellable<F, D, S, E>(handler: F) -> !
+where
+    F: FnMut(D, CancellationToken) -> Result<S, E>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: crate::ErrorTypeName + 'static,
+{
+    let (sender, receiver) = mpsc::channel::<(Instant, CancellationToken)>();
+    std::thread::spawn(move || {
+        // Only the latest invocation's token matters, so a new one replaces any pending one.
+        let mut pending: Option<(Instant, CancellationToken)> = None;
+        loop {
+            let message = match &pending {
+                Some((at, _)) => {
+                    receiver.recv_timeout(at.saturating_duration_since(Instant::now()))
+                }
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match message {
+                Ok(next) => pending = Some(next),
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some((_, token)) = pending.take() {
+                        token.cancel();
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+
+    let mut handler = handler;
+    crate::run_with_context(move |context, event| {
+        let token = CancellationToken::new();
+        let at = context
+            .deadline
+            .to_instant()
+            .checked_sub(MARGIN)
+            .unwrap_or_else(Instant::now);
+        // If the timer thread has gone away, the token is never cancelled, which is no worse than
+        // not having one.
+        let _ = sender.send((at, token.clone()));
+        handler(event, token)
+    })
+}
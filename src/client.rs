@@ -0,0 +1,354 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::error::{Error, Result};
+use crate::http::{self, Endpoint, Headers};
+use crate::transport::{Addr, Transport};
+use crate::{Body, Context, ResponseWriter};
+use serde::Serialize;
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+/// The endpoint of the last runtime built, for reporting initialization errors from code the
+/// runtime isn't handed to, like [`LazyInit`](crate::LazyInit).
+static RUNTIME_ENDPOINT: Mutex<Option<Endpoint>> = Mutex::new(None);
+
+/// A low-level client for the [Lambda runtime API][interface].
+///
+/// The `run` family of functions is built on this client. If you need your own control loop
+/// (custom retries, multiple handlers, instrumentation), you can use it directly:
+///
+/// ```rust,no_run
+/// let client = minlambda::Client::from_env()?;
+/// loop {
+///     let (context, body) = client.next_invocation()?;
+///     let event: serde_json::Value = serde_json::from_reader(body)?;
+///     client.respond(&context.request_id, &event)?;
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Unlike the `run` functions, the client doesn't set [`context()`](crate::context()) for you.
+///
+/// [interface]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+#[derive(Debug, Clone)]
+pub struct Client {
+    endpoint: Endpoint,
+}
+
+impl Client {
+    /// Creates a client for the runtime API at `addr`.
+    #[must_use]
+    pub fn new(addr: SocketAddr) -> Client {
+        Client {
+            endpoint: Endpoint::new(Addr::Tcp(addr)),
+        }
+    }
+
+    /// Creates a client for a runtime API listening on the Unix domain socket at `path`, such as
+    /// a local emulator's.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn new_unix<P: AsRef<Path>>(path: P) -> Client {
+        Client {
+            endpoint: Endpoint::new(Addr::unix(path)),
+        }
+    }
+
+    /// Creates a client for a runtime API listening on the vsock port `port` of the context ID
+    /// `cid`, such as in a Firecracker microVM test rig.
+    ///
+    /// This is available with the `vsock` feature, on Linux.
+    #[cfg(feature = "vsock")]
+    #[must_use]
+    pub fn new_vsock(cid: u32, port: u32) -> Client {
+        Client {
+            endpoint: Endpoint::new(Addr::Vsock { cid, port }),
+        }
+    }
+
+    /// Creates a client that connects to the runtime API with `transport`. See the
+    /// [`transport`](crate::transport) module.
+    #[must_use]
+    pub fn with_transport<T: Transport + 'static>(transport: T) -> Client {
+        Client {
+            endpoint: Endpoint::new(Addr::custom(transport)),
+        }
+    }
+
+    pub(crate) fn with_endpoint(endpoint: Endpoint) -> Client {
+        Client { endpoint }
+    }
+
+    pub(crate) fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Creates a client for the runtime API given by the `AWS_LAMBDA_RUNTIME_API` environment
+    /// variable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the environment variable is missing or cannot be resolved to a
+    /// [`SocketAddr`]. It can be an address or a host name, with a port, or (on Unix) the path to
+    /// a Unix domain socket as a `unix://` URL, like `unix:///tmp/runtime.sock`. With the `vsock`
+    /// feature, it can also be a context ID and port as a `vsock://` URL, like `vsock://2:9001`.
+    pub fn from_env() -> Result<Client> {
+        let addrs = addrs_from_env()?;
+        let mut endpoint = Endpoint::new(addrs[0].clone());
+        endpoint.choose_addr(&addrs);
+        Ok(Client::with_endpoint(endpoint))
+    }
+
+    /// Returns the address of the runtime API.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime API is on a Unix domain socket or vsock port, or reached with a
+    /// [`Transport`], rather than at a TCP address.
+    #[must_use]
+    #[deprecated(since = "0.2.0", note = "use `socket_addr`, which doesn't panic")]
+    pub fn addr(&self) -> SocketAddr {
+        match self.socket_addr() {
+            Some(addr) => addr,
+            None => panic!(
+                "runtime API isn't at a TCP address ({:?})",
+                self.endpoint.addr
+            ),
+        }
+    }
+
+    /// Returns the TCP address of the runtime API, or `None` if it's on a Unix domain socket or
+    /// vsock port, or reached with a [`Transport`].
+    #[must_use]
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match &self.endpoint.addr {
+            Addr::Tcp(addr) => Some(*addr),
+            Addr::Host(host) => Some(host.addr),
+            _ => None,
+        }
+    }
+
+    /// Waits for the next invocation, returning its context and event body.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the response is malformed.
+    pub fn next_invocation(&self) -> Result<(Context, Body)> {
+        http::get_invocation(&self.endpoint, "invocation/next")
+    }
+
+    /// Sends a JSON response for an invocation.
+    ///
+    /// A [`NoResponse`](crate::NoResponse) response is sent as an empty body.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization or the request fails, or
+    /// [`Error::ResponseTooLarge`](crate::Error::ResponseTooLarge) if the response is larger than
+    /// Lambda accepts (6 MB).
+    pub fn respond<S: Serialize>(&self, request_id: &str, response: &S) -> Result<()> {
+        http::post_response(
+            &self.endpoint,
+            &format!("invocation/{request_id}/response"),
+            response,
+        )
+    }
+
+    /// Sends a response for an invocation as raw bytes with the given content type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, or if the response is too large like for
+    /// [`respond`](Client::respond).
+    pub fn respond_raw(&self, request_id: &str, content_type: &str, response: &[u8]) -> Result<()> {
+        http::post_raw_response(
+            &self.endpoint,
+            &format!("invocation/{request_id}/response"),
+            content_type,
+            response,
+        )
+    }
+
+    /// Returns a writer for streaming a response for an invocation.
+    ///
+    /// Call [`ResponseWriter::finish`] once the response is written.
+    #[must_use]
+    pub fn response_writer(&self, request_id: &str) -> ResponseWriter {
+        ResponseWriter::new(
+            self.endpoint.clone(),
+            &format!("invocation/{request_id}/response"),
+        )
+    }
+
+    /// Reports an error for an invocation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn report_error<E>(&self, request_id: &str, err: &E) -> Result<()>
+    where
+        E: crate::ErrorTypeName + 'static,
+    {
+        http::post_error(
+            &self.endpoint,
+            &format!("invocation/{request_id}/error"),
+            err.error_type_name(),
+            &crate::error::message(err),
+        )
+    }
+
+    /// Waits for a [SnapStart] snapshot of the execution environment to be taken and restored.
+    ///
+    /// With [SnapStart], Lambda takes the snapshot once the runtime has made this request, and it's
+    /// answered in each execution environment restored from the snapshot. Runtimes should make it
+    /// once initialization is done, before asking for the first invocation, if the
+    /// `AWS_LAMBDA_INITIALIZATION_TYPE` environment variable is `snap-start`. The `run` functions
+    /// do this for you, calling the [`snapstart`](crate::snapstart) hooks around it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    ///
+    /// [SnapStart]: https://docs.aws.amazon.com/lambda/latest/dg/snapstart.html
+    pub fn restore_next(&self) -> Result<()> {
+        let path = "restore/next";
+        http::api_request(
+            &self.endpoint,
+            "GET",
+            &http::runtime(path),
+            &Headers::new(),
+            &[],
+        )
+        .and_then(|(_, body)| http::drain(body))
+        .map_err(http::context("GET", path))
+    }
+
+    /// Reports an error restoring the execution environment from a [SnapStart] snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    ///
+    /// [SnapStart]: https://docs.aws.amazon.com/lambda/latest/dg/snapstart.html
+    pub fn report_restore_error<E>(&self, err: &E) -> Result<()>
+    where
+        E: crate::ErrorTypeName + 'static,
+    {
+        http::post_error(
+            &self.endpoint,
+            "restore/error",
+            err.error_type_name(),
+            &crate::error::message(err),
+        )
+    }
+
+    /// Reports an initialization error; Lambda will not send any invocations after this.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn report_init_error<E>(&self, err: &E) -> Result<()>
+    where
+        E: crate::ErrorTypeName + 'static,
+    {
+        http::post_error(
+            &self.endpoint,
+            "init/error",
+            err.error_type_name(),
+            &crate::error::message(err),
+        )
+    }
+}
+
+/// Reports an initialization error to the runtime API at `endpoint` and exits.
+pub(crate) fn init_failed(endpoint: &Endpoint, ty: &str, message: &str) -> ! {
+    if let Err(err) = http::post_error(endpoint, "init/error", ty, message) {
+        panic!(
+            "failed to report initialization error: {}\ncaused by: {}",
+            err, message
+        );
+    }
+    std::process::exit(1)
+}
+
+/// Notes the endpoint of a runtime that's been built, for [`lazy_init_failed`].
+pub(crate) fn set_runtime_endpoint(endpoint: Endpoint) {
+    *RUNTIME_ENDPOINT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = Some(endpoint);
+}
+
+/// Reports an initialization error like [`init_failed`], to the last runtime built, or the
+/// runtime API in `AWS_LAMBDA_RUNTIME_API` if none has been.
+pub(crate) fn lazy_init_failed<E>(err: &E) -> !
+where
+    E: crate::ErrorTypeName + 'static,
+{
+    let endpoint = (RUNTIME_ENDPOINT.lock())
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let endpoint = endpoint.unwrap_or_else(|| {
+        Client::from_env()
+            .unwrap_or_else(|err| panic!("{}", err))
+            .endpoint
+    });
+    init_failed(
+        &endpoint,
+        err.error_type_name(),
+        &crate::error::message(err),
+    )
+}
+
+/// Resolves `AWS_LAMBDA_RUNTIME_API`, returning at least one address.
+pub(crate) fn addrs_from_env() -> Result<Vec<Addr>> {
+    let var = std::env::var("AWS_LAMBDA_RUNTIME_API")
+        .map_err(|_| Error::Config(String::from("could not get $AWS_LAMBDA_RUNTIME_API")))?;
+    #[cfg(unix)]
+    if let Some(path) = var.strip_prefix("unix://") {
+        return Ok(vec![Addr::unix(path)]);
+    }
+    #[cfg(feature = "vsock")]
+    if let Some(addr) = var.strip_prefix("vsock://") {
+        let (cid, port) = (addr.split_once(':'))
+            .and_then(|(cid, port)| Some((cid.parse().ok()?, port.parse().ok()?)))
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "could not parse $AWS_LAMBDA_RUNTIME_API ({var:?}) as vsock://CID:PORT"
+                ))
+            })?;
+        return Ok(vec![Addr::Vsock { cid, port }]);
+    }
+    // This accepts host names (e.g. `localhost:9001`) as well as addresses.
+    let addrs: Vec<Addr> = var
+        .to_socket_addrs()
+        .map_err(|err| {
+            Error::Config(format!(
+                "could not resolve $AWS_LAMBDA_RUNTIME_API ({var:?}): {err}"
+            ))
+        })?
+        .map(Addr::Tcp)
+        .collect();
+    if addrs.is_empty() {
+        return Err(Error::Config(format!(
+            "$AWS_LAMBDA_RUNTIME_API ({var:?}) resolved to no addresses"
+        )));
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn socket_addr() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 9001));
+        assert_eq!(Client::new(addr).socket_addr(), Some(addr));
+        #[cfg(unix)]
+        assert_eq!(Client::new_unix("/tmp/runtime.sock").socket_addr(), None);
+    }
+}
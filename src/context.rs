@@ -0,0 +1,214 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::base64;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    static CURRENT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// Information about the current invocation, parsed from the [`invocation/next`][next] response
+/// headers.
+///
+/// [next]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html#runtimes-api-next
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Context {
+    /// The request ID, which identifies the request that triggered the function invocation
+    /// (`Lambda-Runtime-Aws-Request-Id`).
+    pub request_id: String,
+    /// The date that the function times out (`Lambda-Runtime-Deadline-Ms`). If the header is
+    /// missing or invalid, this is 15 minutes, the longest a function can run, after the
+    /// invocation was received.
+    pub deadline: Deadline,
+    /// The ARN of the Lambda function, version, or alias that's specified in the invocation
+    /// (`Lambda-Runtime-Invoked-Function-Arn`), or empty if the header is missing.
+    pub invoked_function_arn: String,
+    /// The AWS X-Ray tracing header, if present (`Lambda-Runtime-Trace-Id`).
+    ///
+    /// AWS SDKs look for this in the `_X_AMZN_TRACE_ID` environment variable by default, which
+    /// minlambda only sets for each invocation if
+    /// [`Builder::trace_id_env`](crate::Builder::trace_id_env) is on, so pass it to them from here.
+    pub trace_id: Option<String>,
+    /// Client context data sent by the AWS Mobile SDK, if present
+    /// (`Lambda-Runtime-Client-Context`).
+    pub client_context: Option<ClientContext>,
+    /// The Amazon Cognito identity provider data sent by the AWS Mobile SDK, if present
+    /// (`Lambda-Runtime-Cognito-Identity`).
+    pub identity: Option<CognitoIdentity>,
+}
+
+/// Client context data sent by the AWS Mobile SDK.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ClientContext {
+    /// Information about the client application.
+    pub client: ClientApplication,
+    /// Custom values set by the client application.
+    pub custom: HashMap<String, String>,
+    /// Environment information provided by the AWS Mobile SDK.
+    pub environment: HashMap<String, String>,
+}
+
+/// Information about the client application that invoked the function.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ClientApplication {
+    /// The installation ID of the application.
+    pub installation_id: String,
+    /// The title of the application.
+    pub app_title: String,
+    /// The version name of the application.
+    pub app_version_name: String,
+    /// The version code of the application.
+    pub app_version_code: String,
+    /// The package name of the application.
+    pub app_package_name: String,
+}
+
+/// The Amazon Cognito identity that authorized the request.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct CognitoIdentity {
+    /// The authenticated Amazon Cognito identity.
+    pub identity_id: String,
+    /// The Amazon Cognito identity pool that authorized the invocation.
+    pub identity_pool_id: String,
+}
+
+impl ClientContext {
+    pub(crate) fn from_header(value: &str) -> Option<ClientContext> {
+        let value = decode_header(value)?;
+        let client = value.get("client");
+        Some(ClientContext {
+            client: ClientApplication {
+                installation_id: get_string(client, "installationId"),
+                app_title: get_string(client, "appTitle"),
+                app_version_name: get_string(client, "appVersionName"),
+                app_version_code: get_string(client, "appVersionCode"),
+                app_package_name: get_string(client, "appPackageName"),
+            },
+            custom: get_map(value.get("custom")),
+            environment: get_map(value.get("env")),
+        })
+    }
+}
+
+impl CognitoIdentity {
+    pub(crate) fn from_header(value: &str) -> Option<CognitoIdentity> {
+        let value = decode_header(value)?;
+        Some(CognitoIdentity {
+            identity_id: get_string(Some(&value), "cognitoIdentityId"),
+            identity_pool_id: get_string(Some(&value), "cognitoIdentityPoolId"),
+        })
+    }
+}
+
+/// Parses a header containing a JSON object, which may or may not be base64-encoded.
+fn decode_header(value: &str) -> Option<Value> {
+    let value = value.trim();
+    let value = if value.starts_with('{') {
+        serde_json::from_str(value)
+    } else {
+        serde_json::from_slice(&base64::decode(value)?)
+    };
+    value.ok().filter(Value::is_object)
+}
+
+fn get_string(value: Option<&Value>, key: &str) -> String {
+    value
+        .and_then(|value| value.get(key))
+        .and_then(Value::as_str)
+        .map(String::from)
+        .unwrap_or_default()
+}
+
+fn get_map(value: Option<&Value>) -> HashMap<String, String> {
+    value
+        .and_then(Value::as_object)
+        .map(|map| {
+            map.iter()
+                .filter_map(|(k, v)| Some((k.clone(), String::from(v.as_str()?))))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The point in time at which Lambda will stop the current invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(u64);
+
+impl Deadline {
+    /// Creates a deadline from a Unix time in milliseconds.
+    #[must_use]
+    pub fn from_unix_millis(millis: u64) -> Deadline {
+        Deadline(millis)
+    }
+
+    /// Returns the deadline of an invocation that's allowed to run as long as Lambda allows any
+    /// function to, from now.
+    pub(crate) fn longest() -> Deadline {
+        let deadline = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            + Duration::from_mins(15);
+        Deadline(u64::try_from(deadline.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// Returns the deadline as a Unix time in milliseconds.
+    #[must_use]
+    pub fn as_unix_millis(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the deadline as a [`SystemTime`].
+    #[must_use]
+    pub fn to_system_time(self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.0)
+    }
+
+    /// Returns the deadline as an [`Instant`], based on the time remaining when called.
+    #[must_use]
+    pub fn to_instant(self) -> Instant {
+        Instant::now() + self.remaining()
+    }
+
+    /// Returns how much time is left before the deadline, or zero if it has passed.
+    #[must_use]
+    pub fn remaining(self) -> Duration {
+        self.to_system_time()
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    }
+}
+
+impl From<Deadline> for SystemTime {
+    fn from(deadline: Deadline) -> SystemTime {
+        deadline.to_system_time()
+    }
+}
+
+impl From<Deadline> for Instant {
+    fn from(deadline: Deadline) -> Instant {
+        deadline.to_instant()
+    }
+}
+
+/// Returns the [`Context`] of the invocation currently being handled, if any.
+///
+/// This is available from anywhere in your handler's call stack without threading the context
+/// through function arguments, but only on the thread that minlambda calls your handler on. Outside
+/// of an invocation (or on other threads), this returns `None`.
+#[must_use]
+pub fn context() -> Option<Context> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+pub(crate) fn set_current(context: Option<Context>) {
+    CURRENT.with(|current| *current.borrow_mut() = context);
+}
@@ -0,0 +1,137 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Deserializing events, like the `serde_json` functions of the same names, and describing events
+//! in error messages.
+//!
+//! With the `serde_path_to_error` feature, errors name the path to the part of the event that
+//! failed to deserialize (e.g. `Records[3].s3.object.key: invalid type: ...`).
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::io::Read;
+
+pub(crate) fn from_slice<'de, T: Deserialize<'de>>(v: &'de [u8]) -> serde_json::Result<T> {
+    let mut de = serde_json::Deserializer::from_slice(v);
+    let value = deserialize(&mut de)?;
+    de.end()?;
+    Ok(value)
+}
+
+pub(crate) fn from_reader<R: Read, T: for<'de> Deserialize<'de>>(
+    reader: R,
+) -> serde_json::Result<T> {
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let value = deserialize(&mut de)?;
+    de.end()?;
+    Ok(value)
+}
+
+pub(crate) fn from_value<T: for<'de> Deserialize<'de>>(value: Value) -> serde_json::Result<T> {
+    deserialize(value)
+}
+
+#[cfg(feature = "serde_path_to_error")]
+fn deserialize<'de, D, T>(de: D) -> serde_json::Result<T>
+where
+    D: Deserializer<'de, Error = serde_json::Error>,
+    T: Deserialize<'de>,
+{
+    serde_path_to_error::deserialize(de).map_err(|err| {
+        let path = err.path().to_string();
+        let err = err.into_inner();
+        if path == "." || err.is_io() {
+            err
+        } else {
+            serde::de::Error::custom(format_args!("{path}: {err}"))
+        }
+    })
+}
+
+#[cfg(not(feature = "serde_path_to_error"))]
+fn deserialize<'de, D, T>(de: D) -> serde_json::Result<T>
+where
+    D: Deserializer<'de, Error = serde_json::Error>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(de)
+}
+
+/// Returns up to `max_len` bytes of an event for an error message, with the values of any object
+/// fields named in `redact` replaced. If there are fields to redact, events that aren't valid JSON
+/// aren't included at all.
+pub(crate) fn snippet(event: &[u8], max_len: usize, redact: &[String]) -> Option<String> {
+    let mut snippet = if redact.is_empty() {
+        String::from_utf8_lossy(event).into_owned()
+    } else {
+        let mut value: Value = serde_json::from_slice(event).ok()?;
+        redact_fields(&mut value, redact);
+        value.to_string()
+    };
+    if snippet.len() > max_len {
+        let mut len = max_len;
+        while !snippet.is_char_boundary(len) {
+            len -= 1;
+        }
+        snippet.truncate(len);
+        snippet.push_str("...");
+    }
+    Some(snippet)
+}
+
+fn redact_fields(value: &mut Value, redact: &[String]) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if redact.contains(key) {
+                    *value = Value::from("[redacted]");
+                } else {
+                    redact_fields(value, redact);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                redact_fields(value, redact);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::snippet;
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            snippet(b"{\"id\":1}", 100, &[]).as_deref(),
+            Some("{\"id\":1}")
+        );
+        assert_eq!(snippet(b"{\"id\":1}", 4, &[]).as_deref(), Some("{\"id..."));
+        // Truncated at a character boundary, and invalid UTF-8 is replaced rather than rejected.
+        assert_eq!(
+            snippet("\"é\"".as_bytes(), 2, &[]).as_deref(),
+            Some("\"...")
+        );
+        assert_eq!(snippet(b"\xff", 10, &[]).as_deref(), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn redacted() {
+        let redact = [String::from("password"), String::from("token")];
+        let event = br#"{"user":"a","password":{"old":"x"},"sessions":[{"token":"y","id":2}]}"#;
+        assert_eq!(
+            snippet(event, 200, &redact).as_deref(),
+            Some(
+                r#"{"password":"[redacted]","sessions":[{"id":2,"token":"[redacted]"}],"user":"a"}"#
+            )
+        );
+        assert_eq!(snippet(b"{\"password\":", 200, &redact), None);
+        assert_eq!(
+            snippet(br#"{"password":"x"}"#, 12, &redact).as_deref(),
+            Some(r#"{"password":..."#)
+        );
+    }
+}
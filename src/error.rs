@@ -0,0 +1,786 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::borrow::Cow;
+use std::fmt::{self, Display};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+/// An error talking to the runtime API, or to another server with [`http`](crate::http).
+///
+/// This is returned by [`Client`](crate::Client) and the other lower-level APIs. It converts into
+/// an [`io::Error`] for code that deals in those.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The runtime couldn't be configured from the environment (e.g. `AWS_LAMBDA_RUNTIME_API` is
+    /// missing or malformed), or an HTTP client couldn't be created for a URL.
+    Config(String),
+    /// Connecting to, reading from, or writing to the runtime API (or another server) failed.
+    Transport(io::Error),
+    /// The runtime API (or another server) sent a response minlambda doesn't understand.
+    Protocol(String),
+    /// An invocation event couldn't be deserialized. With the `serde_path_to_error` feature, the
+    /// error's message starts with the path to the part of the event that failed.
+    Deserialize(serde_json::Error),
+    /// A response couldn't be serialized.
+    Serialize(serde_json::Error),
+    /// The runtime API responded with an error status.
+    RuntimeApi {
+        /// The HTTP status code.
+        status: u16,
+        /// The response body; for the runtime API's own errors, this is a JSON object with
+        /// `errorType` and `errorMessage` fields.
+        body: String,
+    },
+    /// An invocation event was larger than
+    /// [`Builder::max_event_size`](crate::Builder::max_event_size) allows.
+    PayloadTooLarge {
+        /// The most bytes allowed.
+        limit: usize,
+    },
+    /// An invocation's response was larger than Lambda accepts, so it wasn't sent (or wasn't sent
+    /// in full).
+    ResponseTooLarge {
+        /// The most bytes allowed.
+        limit: usize,
+    },
+}
+
+impl Error {
+    pub(crate) fn protocol(message: &str) -> Error {
+        Error::Protocol(String::from(message))
+    }
+
+    /// Wraps an error from a connection. Sockets report their read and write timeouts as
+    /// `WouldBlock` on some platforms, so those become `TimedOut` errors that say so.
+    /// Errors from reading a [`Body`](crate::Body) that were ours to begin with are unwrapped.
+    pub(crate) fn transport(err: io::Error) -> Error {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        if err.kind() == io::ErrorKind::WouldBlock {
+            Error::Transport(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for the server",
+            ))
+        } else {
+            Error::Transport(err)
+        }
+    }
+
+    /// Adds what was being done to a transport error, so that its message says which request
+    /// failed and how far it got, like "while posting the response for request abc-123". The
+    /// error keeps its [`io::ErrorKind`].
+    pub(crate) fn context(self, context: impl FnOnce() -> String) -> Error {
+        match self {
+            Error::Transport(err) => Error::Transport(io::Error::new(
+                err.kind(),
+                Context {
+                    context: context(),
+                    source: err,
+                },
+            )),
+            err => err,
+        }
+    }
+
+    /// Returns whether making the request again might succeed: it failed because of the
+    /// connection, or the server responded that it's overloaded or failed. Other errors, such as a
+    /// body that can't be serialized, would happen the same way every time.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            Error::Transport(_) => true,
+            Error::RuntimeApi { status, .. } => crate::http::retryable_status(*status),
+            _ => false,
+        }
+    }
+
+    /// Classifies an error from serializing a response.
+    pub(crate) fn serialize(err: serde_json::Error) -> Error {
+        if err.is_io() {
+            Error::transport(err.into())
+        } else {
+            Error::Serialize(err)
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => f.write_str(message),
+            Error::Transport(err) => write!(f, "HTTP request failed: {err}"),
+            Error::Protocol(message) => write!(f, "malformed HTTP response: {message}"),
+            Error::Deserialize(err) => write!(f, "failed to deserialize event: {err}"),
+            Error::Serialize(err) => write!(f, "failed to serialize response: {err}"),
+            Error::RuntimeApi { status, body } if body.is_empty() => {
+                write!(f, "runtime API responded with HTTP {status}")
+            }
+            Error::RuntimeApi { status, body } => match runtime_api_error(body) {
+                Some((Some(ty), message)) => {
+                    write!(
+                        f,
+                        "runtime API responded with HTTP {status}: {ty}: {message}"
+                    )
+                }
+                Some((None, message)) => {
+                    write!(f, "runtime API responded with HTTP {status}: {message}")
+                }
+                None => write!(f, "runtime API responded with HTTP {status}: {body}"),
+            },
+            Error::ResponseTooLarge { limit } => {
+                write!(f, "response is larger than Lambda's limit of {limit} bytes")
+            }
+            Error::PayloadTooLarge { limit } => {
+                write!(
+                    f,
+                    "invocation event is larger than the limit of {limit} bytes"
+                )
+            }
+        }
+    }
+}
+
+/// A transport error, along with what was being done when it happened.
+#[derive(Debug)]
+struct Context {
+    context: String,
+    source: io::Error,
+}
+
+impl Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.source)
+    }
+}
+
+impl std::error::Error for Context {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Picks the `errorType` and `errorMessage` out of an error body from the runtime API, if it's
+/// shaped like one of its own.
+fn runtime_api_error(body: &str) -> Option<(Option<String>, String)> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    let message = value.get("errorMessage")?.as_str()?;
+    let ty = (value.get("errorType"))
+        .and_then(serde_json::Value::as_str)
+        .filter(|ty| !ty.is_empty());
+    Some((ty.map(String::from), String::from(message)))
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(err) => Some(err),
+            Error::Deserialize(err) | Error::Serialize(err) => Some(err),
+            Error::Config(_)
+            | Error::Protocol(_)
+            | Error::RuntimeApi { .. }
+            | Error::PayloadTooLarge { .. }
+            | Error::ResponseTooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::transport(err)
+    }
+}
+
+// This classifies errors as if they came from deserializing an event; use `Error::serialize` for
+// serialization errors.
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        if err.is_io() {
+            Error::transport(err.into())
+        } else {
+            Error::Deserialize(err)
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Transport(err) => err,
+            Error::Config(_) => io::Error::new(io::ErrorKind::InvalidInput, err),
+            Error::RuntimeApi { .. } => io::Error::other(err),
+            Error::Protocol(_)
+            | Error::Deserialize(_)
+            | Error::Serialize(_)
+            | Error::PayloadTooLarge { .. }
+            | Error::ResponseTooLarge { .. } => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// An error as reported to the runtime API.
+///
+/// This serializes as the error body Lambda expects, with `errorType`, `errorMessage`, and (if
+/// there is one) `stackTrace` fields, along with `requestId`, `functionVersion`, and
+/// `invocationCount` fields if [`Builder::error_metadata`](crate::Builder::error_metadata) is
+/// enabled. A [`Builder::error_body`](crate::Builder::error_body)
+/// function receives it to build a different body.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct ErrorReport<'a> {
+    /// The error type; see [`ErrorTypeName`].
+    pub error_type: &'a str,
+    /// The error message, including the error's source chain.
+    pub error_message: &'a str,
+    /// The frames of the error's backtrace, one per entry, if it has one; see [`Traced`].
+    pub stack_trace: &'a [String],
+    /// The request ID of the invocation, if error metadata is enabled.
+    pub request_id: Option<&'a str>,
+    /// The function version from the `AWS_LAMBDA_FUNCTION_VERSION` environment variable, if error
+    /// metadata is enabled.
+    pub function_version: Option<&'a str>,
+    /// How many invocations this execution environment has received, including this one, if error
+    /// metadata is enabled.
+    pub invocation_count: Option<u64>,
+}
+
+impl<'a> ErrorReport<'a> {
+    pub(crate) fn new(error_type: &'a str, error_message: &'a str) -> ErrorReport<'a> {
+        ErrorReport {
+            error_type,
+            error_message,
+            stack_trace: &[],
+            request_id: None,
+            function_version: None,
+            invocation_count: None,
+        }
+    }
+}
+
+impl Serialize for ErrorReport<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let len = 2
+            + usize::from(!self.stack_trace.is_empty())
+            + usize::from(self.request_id.is_some())
+            + usize::from(self.function_version.is_some())
+            + usize::from(self.invocation_count.is_some());
+        let mut s = serializer.serialize_struct("ErrorReport", len)?;
+        s.serialize_field("errorType", self.error_type)?;
+        s.serialize_field("errorMessage", self.error_message)?;
+        if !self.stack_trace.is_empty() {
+            s.serialize_field("stackTrace", self.stack_trace)?;
+        }
+        if let Some(request_id) = self.request_id {
+            s.serialize_field("requestId", request_id)?;
+        }
+        if let Some(function_version) = self.function_version {
+            s.serialize_field("functionVersion", function_version)?;
+        }
+        if let Some(invocation_count) = self.invocation_count {
+            s.serialize_field("invocationCount", &invocation_count)?;
+        }
+        s.end()
+    }
+}
+
+/// The `errorType` reported to the runtime API for an error, and the error's source chain.
+///
+/// Handler errors need to implement this trait, whose methods all have default implementations: by
+/// default, an error is reported with its Rust type name (e.g. `my_function::OrderError`) as its
+/// `errorType`, which changes whenever the code is refactored. To report stable names instead,
+/// implement [`error_type_name`](ErrorTypeName::error_type_name):
+///
+/// ```rust,no_run
+/// #[derive(Debug)]
+/// enum OrderError {
+///     NotFound,
+///     OutOfStock,
+/// }
+///
+/// impl std::fmt::Display for OrderError {
+///     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+///         f.write_str(match self {
+///             OrderError::NotFound => "no such order",
+///             OrderError::OutOfStock => "item out of stock",
+///         })
+///     }
+/// }
+///
+/// impl minlambda::ErrorTypeName for OrderError {
+///     fn error_type_name(&self) -> &str {
+///         match self {
+///             OrderError::NotFound => "OrderNotFound",
+///             OrderError::OutOfStock => "OutOfStock",
+///         }
+///     }
+/// }
+///
+/// fn main() -> ! {
+///     minlambda::run(|order: u64| -> Result<String, OrderError> {
+///         Err(OrderError::NotFound)
+///     })
+/// }
+/// ```
+///
+/// The reported `errorMessage` is the error's `Display` followed by its
+/// [`error_source`](ErrorTypeName::error_source) and each error in that error's
+/// [`source`](std::error::Error::source) chain, separated by `: ` (e.g. `failed to fetch config:
+/// connection refused`). Messages longer than 64 KiB are truncated. Error types that implement
+/// [`std::error::Error`] should implement `error_source` to return their source, so that the rest
+/// of the chain is found:
+///
+/// ```rust
+/// # #[derive(Debug)]
+/// # struct ConfigError(std::io::Error);
+/// # impl std::fmt::Display for ConfigError {
+/// #     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// #         f.write_str("failed to fetch config")
+/// #     }
+/// # }
+/// impl std::error::Error for ConfigError {
+///     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+///         Some(&self.0)
+///     }
+/// }
+///
+/// impl minlambda::ErrorTypeName for ConfigError {
+///     fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+///         std::error::Error::source(self)
+///     }
+/// }
+/// ```
+///
+/// Errors that are boxed as `Box<dyn std::error::Error>` have their whole source chain reported
+/// without this.
+///
+/// minlambda implements this trait for its own [`Error`], many standard library error types, boxed
+/// errors, and strings, reporting their source chains. With the `anyhow` feature, the same goes
+/// for `anyhow::Error`, so handlers can return `anyhow::Result`; the error's context strings and
+/// causes are all included in the message. Error types from other crates that only implement
+/// [`Display`] can be returned by handlers run with [`run_display`](crate::run_display).
+pub trait ErrorTypeName: Display {
+    /// Returns the error type to report.
+    fn error_type_name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Returns the lower-level cause of this error, usually [`std::error::Error::source`]. The
+    /// default implementation returns `None`.
+    fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+
+    /// Returns `false` if the process should exit after this error is reported, so that Lambda
+    /// starts a fresh execution environment for the next invocation (e.g. because global state may
+    /// be corrupt). The default implementation returns `true`; see also [`Fatal`].
+    fn is_recoverable(&self) -> bool {
+        true
+    }
+
+    /// Returns a backtrace of where this error was created, to report as the error's
+    /// `stackTrace`. The default implementation returns `None`; see also [`Traced`].
+    fn error_backtrace(&self) -> Option<&Backtrace> {
+        None
+    }
+}
+
+/// Wraps an error to make it unrecoverable: once it's reported, the process exits so that Lambda
+/// replaces the execution environment.
+///
+/// ```rust,no_run
+/// use minlambda::Fatal;
+///
+/// fn main() -> ! {
+///     minlambda::run(|path: String| {
+///         std::fs::read_to_string(path).map_err(Fatal::new)
+///     })
+/// }
+/// ```
+///
+/// The error is otherwise reported as it would be without the wrapper.
+#[derive(Debug, Clone, Copy)]
+pub struct Fatal<E>(E);
+
+impl<E> Fatal<E> {
+    /// Wraps `err`.
+    pub fn new(err: E) -> Fatal<E> {
+        Fatal(err)
+    }
+
+    /// Returns the wrapped error.
+    pub fn into_inner(self) -> E {
+        self.0
+    }
+}
+
+impl<E: Display> Display for Fatal<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Fatal<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl<E: ErrorTypeName> ErrorTypeName for Fatal<E> {
+    fn error_type_name(&self) -> &str {
+        self.0.error_type_name()
+    }
+
+    fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.error_source()
+    }
+
+    fn is_recoverable(&self) -> bool {
+        false
+    }
+
+    fn error_backtrace(&self) -> Option<&Backtrace> {
+        self.0.error_backtrace()
+    }
+}
+
+/// Wraps an error with a backtrace of where it was wrapped, which is reported as the error's
+/// `stackTrace`.
+///
+/// ```rust,no_run
+/// use minlambda::Traced;
+///
+/// fn main() -> ! {
+///     minlambda::run(|path: String| {
+///         std::fs::read_to_string(path).map_err(Traced::new)
+///     })
+/// }
+/// ```
+///
+/// Like [`std::backtrace::Backtrace::capture`], this only captures a backtrace if the
+/// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables enable them, unless
+/// [`Builder::backtraces`](crate::Builder::backtraces) is set. With the `anyhow` feature,
+/// `anyhow::Error`'s own backtrace is reported without this wrapper.
+///
+/// The error is otherwise reported as it would be without the wrapper.
+#[derive(Debug)]
+pub struct Traced<E> {
+    err: E,
+    backtrace: Backtrace,
+}
+
+impl<E> Traced<E> {
+    /// Wraps `err`, capturing a backtrace.
+    pub fn new(err: E) -> Traced<E> {
+        let backtrace = if FORCE_BACKTRACES.load(Ordering::Relaxed) {
+            Backtrace::force_capture()
+        } else {
+            Backtrace::capture()
+        };
+        Traced { err, backtrace }
+    }
+
+    /// Returns the wrapped error.
+    pub fn into_inner(self) -> E {
+        self.err
+    }
+
+    /// Returns the backtrace captured when the error was wrapped.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl<E: Display> Display for Traced<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.err.fmt(f)
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Traced<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.err.source()
+    }
+}
+
+impl<E: ErrorTypeName> ErrorTypeName for Traced<E> {
+    fn error_type_name(&self) -> &str {
+        self.err.error_type_name()
+    }
+
+    fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.err.error_source()
+    }
+
+    fn is_recoverable(&self) -> bool {
+        self.err.is_recoverable()
+    }
+
+    fn error_backtrace(&self) -> Option<&Backtrace> {
+        Some(&self.backtrace)
+    }
+}
+
+/// Reports an error with its `Display` and Rust type name alone, for
+/// [`run_display`](crate::run_display).
+#[derive(Debug)]
+pub(crate) struct Displayed<E>(pub(crate) E);
+
+impl<E: Display> Display for Displayed<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<E: Display> ErrorTypeName for Displayed<E> {
+    fn error_type_name(&self) -> &str {
+        std::any::type_name::<E>()
+    }
+}
+
+/// Set by [`Builder::backtraces`](crate::Builder::backtraces).
+static FORCE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+/// Makes [`Traced`] capture backtraces regardless of the environment.
+pub(crate) fn force_backtraces() {
+    FORCE_BACKTRACES.store(true, Ordering::Relaxed);
+}
+
+/// Returns the frames of `err`'s backtrace, if it has one, one per entry (e.g. `my_function at
+/// src/main.rs:12:5`).
+pub(crate) fn stack_trace<E: ErrorTypeName + ?Sized>(err: &E) -> Vec<String> {
+    let Some(backtrace) = err.error_backtrace() else {
+        return Vec::new();
+    };
+    if backtrace.status() != BacktraceStatus::Captured {
+        return Vec::new();
+    }
+    // Each frame is displayed as `N: symbol`, followed by an indented `at file:line:column` line
+    // if its location is known.
+    let mut frames: Vec<String> = Vec::new();
+    for line in backtrace.to_string().lines() {
+        let line = line.trim();
+        if let (Some(location), Some(frame)) = (line.strip_prefix("at "), frames.last_mut()) {
+            frame.push_str(" at ");
+            frame.push_str(location);
+        } else if let Some((index, symbol)) = line.split_once(": ") {
+            if index.bytes().all(|b| b.is_ascii_digit()) {
+                frames.push(symbol.to_owned());
+            }
+        }
+    }
+    frames
+}
+
+/// The longest `errorMessage` minlambda reports, in bytes, so that error reports stay well under
+/// the runtime API's request size limits.
+pub(crate) const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+const TRUNCATED: &str = " (truncated)";
+
+/// Truncates a message longer than [`MAX_MESSAGE_LEN`], marking that it was truncated.
+pub(crate) fn truncate(mut message: String) -> String {
+    if message.len() > MAX_MESSAGE_LEN {
+        let mut len = MAX_MESSAGE_LEN - TRUNCATED.len();
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        message.truncate(len);
+        message.push_str(TRUNCATED);
+    }
+    message
+}
+
+/// Returns the messages of an error and each error in its source chain.
+#[cfg(feature = "reporting")]
+pub(crate) fn chain<E: ErrorTypeName + ?Sized>(err: &E) -> Vec<String> {
+    let mut chain = vec![err.to_string()];
+    let mut source = err.error_source();
+    while let Some(err) = source {
+        chain.push(err.to_string());
+        source = err.source();
+    }
+    chain
+}
+
+/// Formats an error and its source chain, skipping sources whose message the error already
+/// includes, and truncates the result.
+pub(crate) fn message<E: ErrorTypeName + ?Sized>(err: &E) -> String {
+    let mut message = err.to_string();
+    let mut source = err.error_source();
+    while let Some(err) = source {
+        let next = err.to_string();
+        if !message.ends_with(&next) {
+            message.push_str(": ");
+            message.push_str(&next);
+        }
+        source = err.source();
+    }
+    truncate(message)
+}
+
+/// Implements [`ErrorTypeName`] for error types whose source chains should be reported.
+macro_rules! error_types {
+    (errors: [$($ty:ty,)*], boxed: [$($boxed:ty,)*],) => {
+        $(impl ErrorTypeName for $ty {
+            fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                std::error::Error::source(self)
+            }
+        })*
+
+        $(impl ErrorTypeName for $boxed {
+            fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                (**self).source()
+            }
+        })*
+    };
+}
+
+#[cfg(feature = "anyhow")]
+impl ErrorTypeName for anyhow::Error {
+    fn error_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        (**self).source()
+    }
+
+    fn error_backtrace(&self) -> Option<&Backtrace> {
+        Some(self.backtrace())
+    }
+}
+
+impl ErrorTypeName for String {}
+impl ErrorTypeName for &str {}
+impl ErrorTypeName for Cow<'_, str> {}
+
+error_types! {
+    errors: [
+        Error,
+        std::convert::Infallible,
+        std::env::VarError,
+        std::fmt::Error,
+        std::io::Error,
+        std::net::AddrParseError,
+        std::num::ParseFloatError,
+        std::num::ParseIntError,
+        std::num::TryFromIntError,
+        std::str::ParseBoolError,
+        std::str::Utf8Error,
+        std::string::FromUtf8Error,
+        std::time::SystemTimeError,
+        serde_json::Error,
+    ],
+    boxed: [
+        Box<dyn std::error::Error>,
+        Box<dyn std::error::Error + Send>,
+        Box<dyn std::error::Error + Send + Sync>,
+    ],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message, Displayed, ErrorTypeName, Fatal, Traced};
+    use std::error::Error;
+    use std::fmt::{self, Display};
+    use std::io;
+
+    #[derive(Debug)]
+    struct FetchError(io::Error);
+
+    impl Display for FetchError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("failed to fetch config")
+        }
+    }
+
+    impl Error for FetchError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[derive(Debug)]
+    struct OrderNotFound;
+
+    impl Display for OrderNotFound {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("no such order")
+        }
+    }
+
+    impl ErrorTypeName for OrderNotFound {
+        fn error_type_name(&self) -> &'static str {
+            "OrderNotFound"
+        }
+    }
+
+    #[derive(Debug)]
+    struct LoadError(FetchError);
+
+    impl Display for LoadError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("failed to load config")
+        }
+    }
+
+    impl ErrorTypeName for LoadError {
+        fn error_source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn fetch_error() -> Box<dyn Error + Send + Sync> {
+        Box::new(FetchError(io::Error::other("connection refused")))
+    }
+
+    #[test]
+    fn error_type_name() {
+        assert_eq!(OrderNotFound.error_type_name(), "OrderNotFound");
+        assert!(OrderNotFound.is_recoverable());
+        let err = Fatal::new(OrderNotFound);
+        assert_eq!(err.error_type_name(), "OrderNotFound");
+        assert!(!err.is_recoverable());
+        let err = Traced::new(Fatal::new(OrderNotFound));
+        assert_eq!(err.error_type_name(), "OrderNotFound");
+        assert!(!err.is_recoverable());
+        assert!(err.error_backtrace().is_some());
+        assert_eq!(
+            String::from("oops").error_type_name(),
+            std::any::type_name::<String>()
+        );
+    }
+
+    #[test]
+    fn source_chain() {
+        let err = LoadError(FetchError(io::Error::other("connection refused")));
+        assert_eq!(
+            message(&err),
+            "failed to load config: failed to fetch config: connection refused"
+        );
+        // Sources whose messages are already included aren't repeated.
+        let err = super::Error::Transport(io::Error::other("connection refused"));
+        assert_eq!(message(&err), "HTTP request failed: connection refused");
+        assert_eq!(
+            message(&io::Error::other(fetch_error())),
+            "failed to fetch config: connection refused"
+        );
+    }
+
+    #[test]
+    fn displayed() {
+        assert_eq!(
+            message(&fetch_error()),
+            "failed to fetch config: connection refused"
+        );
+        let err = Displayed(fetch_error());
+        assert_eq!(message(&err), "failed to fetch config");
+        assert_eq!(
+            err.error_type_name(),
+            std::any::type_name::<Box<dyn Error + Send + Sync>>()
+        );
+    }
+}
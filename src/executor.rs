@@ -0,0 +1,89 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Executors for driving future-returning handlers.
+//!
+//! minlambda doesn't care which async runtime you use; it only needs something that can block the
+//! current thread on a future. [`BlockOn`] is always available. Adapters for other runtimes are
+//! available with the `tokio`, `smol`, and `async-std` features.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+
+/// Something that can run a future to completion on the current thread.
+pub trait Executor {
+    /// Blocks the current thread until `future` completes, returning its output.
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output;
+}
+
+impl<X: Executor + ?Sized> Executor for &mut X {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        (**self).block_on(future)
+    }
+}
+
+/// A tiny built-in executor that polls a single future, parking the thread while it is pending.
+///
+/// There is no I/O or timer driver, so futures that depend on a particular runtime will not make
+/// progress.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockOn;
+
+impl Executor for BlockOn {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Executor for tokio::runtime::Runtime {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        tokio::runtime::Runtime::block_on(self, future)
+    }
+}
+
+/// Runs futures with [smol](https://docs.rs/smol)'s `block_on`, which drives its reactor.
+#[cfg(feature = "smol")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Smol;
+
+#[cfg(feature = "smol")]
+impl Executor for Smol {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        smol::block_on(future)
+    }
+}
+
+/// Runs futures with [async-std](https://docs.rs/async-std)'s `task::block_on`.
+#[cfg(feature = "async-std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsyncStd;
+
+#[cfg(feature = "async-std")]
+impl Executor for AsyncStd {
+    fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        async_std::task::block_on(future)
+    }
+}
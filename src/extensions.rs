@@ -0,0 +1,323 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! A client for the [Lambda Extensions API][extensions], for writing extensions.
+//!
+//! An extension registers with the API, then asks for events in a loop: an [`Event::Invoke`] for
+//! each invocation of the function, and an [`Event::Shutdown`] once before Lambda shuts the
+//! execution environment down. Asking for the next event tells Lambda the extension is done with
+//! the last one.
+//!
+//! ```rust,no_run
+//! use minlambda::extensions::{Event, EventType, Extension};
+//!
+//! let extension = Extension::register("my-extension", &[EventType::Invoke, EventType::Shutdown])?;
+//! loop {
+//!     match extension.next_event()? {
+//!         Event::Invoke { request_id, .. } => eprintln!("invoked: {request_id}"),
+//!         Event::Shutdown { reason, .. } => {
+//!             eprintln!("shutting down: {reason:?}");
+//!             break;
+//!         }
+//!         _ => {}
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! External extensions are separate executables in the `/opt/extensions` directory of a layer,
+//! and register with the name of their executable. Internal extensions run in the function's own
+//! process, usually on a thread of their own, and register with any name; they must register
+//! before the runtime asks for its first invocation, and can't register for shutdown events.
+//!
+//! [extensions]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-extensions-api.html
+
+use crate::client::Client;
+use crate::context::Deadline;
+use crate::error::{Error, ErrorReport, Result};
+use crate::http::{self, Endpoint, Headers};
+use serde_json::Value;
+use std::path::PathBuf;
+
+const REGISTER: &str = "/2020-01-01/extension/register";
+const EVENT_NEXT: &str = "/2020-01-01/extension/event/next";
+const INIT_ERROR: &str = "/2020-01-01/extension/init/error";
+const EXIT_ERROR: &str = "/2020-01-01/extension/exit/error";
+
+/// Returns the file name of the running executable, which is the name an external extension has
+/// to register with.
+pub(crate) fn executable_name() -> Option<String> {
+    // `current_exe` follows symbolic links, but the name has to be the one in `/opt/extensions`.
+    let path = std::env::args_os()
+        .next()
+        .map(PathBuf::from)
+        .or_else(|| std::env::current_exe().ok())?;
+    Some(path.file_name()?.to_string_lossy().into_owned())
+}
+
+/// The kinds of event an extension can register for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EventType {
+    /// An invocation of the function.
+    Invoke,
+    /// The shutdown of the execution environment. Internal extensions can't register for these.
+    Shutdown,
+}
+
+impl EventType {
+    fn as_str(self) -> &'static str {
+        match self {
+            EventType::Invoke => "INVOKE",
+            EventType::Shutdown => "SHUTDOWN",
+        }
+    }
+}
+
+/// An event from the Extensions API.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// The function was invoked.
+    Invoke {
+        /// The request ID of the invocation.
+        request_id: String,
+        /// The date that the invocation times out.
+        deadline: Deadline,
+        /// The ARN of the Lambda function, version, or alias that's specified in the invocation.
+        invoked_function_arn: String,
+        /// The AWS X-Ray tracing header of the invocation, if there is one.
+        trace_id: Option<String>,
+    },
+    /// The execution environment is shutting down. This is the last event an extension gets.
+    Shutdown {
+        /// Why the execution environment is shutting down.
+        reason: ShutdownReason,
+        /// The date by which the extension must have exited.
+        deadline: Deadline,
+    },
+}
+
+/// Why an execution environment is shutting down.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ShutdownReason {
+    /// The execution environment wasn't needed anymore.
+    Spindown,
+    /// The function, or an extension, timed out.
+    Timeout,
+    /// The function, or an extension, failed.
+    Failure,
+    /// A reason minlambda doesn't know about.
+    Other(String),
+}
+
+impl ShutdownReason {
+    fn parse(reason: &str) -> ShutdownReason {
+        match reason {
+            "spindown" => ShutdownReason::Spindown,
+            "timeout" => ShutdownReason::Timeout,
+            "failure" => ShutdownReason::Failure,
+            _ => ShutdownReason::Other(String::from(reason)),
+        }
+    }
+}
+
+/// An extension registered with the Extensions API.
+#[derive(Debug, Clone)]
+pub struct Extension {
+    endpoint: Endpoint,
+    id: String,
+    function_name: String,
+    function_version: String,
+    handler: String,
+    account_id: Option<String>,
+}
+
+impl Extension {
+    /// Registers an extension named `name` for `events`, with the Extensions API given by the
+    /// `AWS_LAMBDA_RUNTIME_API` environment variable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the environment variable is missing or malformed (like for
+    /// [`Client::from_env`]), or if registration fails.
+    pub fn register(name: &str, events: &[EventType]) -> Result<Extension> {
+        Extension::register_with(&Client::from_env()?, name, events)
+    }
+
+    /// Registers an extension named `name` for `events`, with the Extensions API served alongside
+    /// the runtime API that `client` connects to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if registration fails.
+    pub fn register_with(client: &Client, name: &str, events: &[EventType]) -> Result<Extension> {
+        let endpoint = client.endpoint().clone();
+        let events = events.iter().map(|ty| ty.as_str()).collect::<Vec<_>>();
+        let body = serde_json::to_vec(&serde_json::json!({ "events": events }))
+            .map_err(Error::serialize)?;
+        let headers = Headers::new()
+            .with("lambda-extension-name", name)
+            .with("lambda-extension-accept-feature", "accountId");
+        let (headers, body) = http::api_request(&endpoint, "POST", REGISTER, &headers, &body)
+            .map_err(http::context("POST", REGISTER))?;
+        let id = (headers.get("lambda-extension-identifier"))
+            .ok_or_else(|| Error::protocol("missing Lambda-Extension-Identifier header"))?;
+        let value: Value = serde_json::from_reader(body)?;
+        let get = |key| value.get(key).and_then(Value::as_str).map(String::from);
+        Ok(Extension {
+            endpoint,
+            id: String::from(id),
+            function_name: get("functionName").unwrap_or_default(),
+            function_version: get("functionVersion").unwrap_or_default(),
+            handler: get("handler").unwrap_or_default(),
+            account_id: get("accountId"),
+        })
+    }
+
+    /// Returns the identifier Lambda gave the extension when it registered
+    /// (`Lambda-Extension-Identifier`).
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the name of the function the extension is running alongside.
+    #[must_use]
+    pub fn function_name(&self) -> &str {
+        &self.function_name
+    }
+
+    /// Returns the version of the function the extension is running alongside.
+    #[must_use]
+    pub fn function_version(&self) -> &str {
+        &self.function_version
+    }
+
+    /// Returns the function's handler setting.
+    #[must_use]
+    pub fn handler(&self) -> &str {
+        &self.handler
+    }
+
+    /// Returns the ID of the AWS account the function belongs to, if Lambda sent it.
+    #[must_use]
+    pub fn account_id(&self) -> Option<&str> {
+        self.account_id.as_deref()
+    }
+
+    pub(crate) fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Waits for the next event, which also tells Lambda the extension is done with the last one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the event is malformed.
+    pub fn next_event(&self) -> Result<Event> {
+        let headers = Headers::new().with("lambda-extension-identifier", self.id.as_str());
+        let (_, body) = http::api_request(&self.endpoint, "GET", EVENT_NEXT, &headers, &[])
+            .map_err(http::context("GET", EVENT_NEXT))?;
+        let value: Value = serde_json::from_reader(body)?;
+        let get = |key| value.get(key).and_then(Value::as_str);
+        let deadline = (value.get("deadlineMs"))
+            .and_then(Value::as_u64)
+            .map(Deadline::from_unix_millis)
+            .ok_or_else(|| Error::protocol("missing deadlineMs in extension event"))?;
+        match get("eventType") {
+            Some("INVOKE") => Ok(Event::Invoke {
+                request_id: String::from(get("requestId").ok_or_else(|| {
+                    Error::protocol("missing requestId in extension INVOKE event")
+                })?),
+                deadline,
+                invoked_function_arn: String::from(get("invokedFunctionArn").unwrap_or_default()),
+                trace_id: (value.get("tracing"))
+                    .and_then(|tracing| tracing.get("value"))
+                    .and_then(Value::as_str)
+                    .map(String::from),
+            }),
+            Some("SHUTDOWN") => Ok(Event::Shutdown {
+                reason: ShutdownReason::parse(get("shutdownReason").unwrap_or_default()),
+                deadline,
+            }),
+            Some(ty) => Err(Error::Protocol(format!(
+                "unknown extension event type {ty:?}"
+            ))),
+            None => Err(Error::protocol("missing eventType in extension event")),
+        }
+    }
+
+    /// Calls `handler` with each event, like [`run_extension`](crate::run_extension), until it's
+    /// called with the shutdown event and the process exits. This doesn't return.
+    ///
+    /// # Panics
+    ///
+    /// Panics if getting an event, or reporting the handler's error, fails.
+    pub fn run<F, E>(&self, handler: F) -> !
+    where
+        F: FnMut(Event) -> std::result::Result<(), E>,
+        E: crate::ErrorTypeName + 'static,
+    {
+        let mut handler = handler;
+        loop {
+            let event = (self.next_event())
+                .unwrap_or_else(|err| panic!("failed to get the next extension event: {}", err));
+            let shutdown = matches!(event, Event::Shutdown { .. });
+            if let Err(err) = handler(event) {
+                if let Err(report_err) = self.report_exit_error(&err) {
+                    panic!(
+                        "failed to report extension exit error: {}\ncaused by: {}",
+                        report_err, err
+                    );
+                }
+                std::process::exit(1);
+            }
+            if shutdown {
+                std::process::exit(0);
+            }
+        }
+    }
+
+    /// Reports that the extension failed to initialize; Lambda will shut the execution
+    /// environment down. The extension should exit after this.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn report_init_error<E>(&self, err: &E) -> Result<()>
+    where
+        E: crate::ErrorTypeName + 'static,
+    {
+        self.post_error(INIT_ERROR, err)
+    }
+
+    /// Reports that the extension failed and is about to exit; Lambda will shut the execution
+    /// environment down.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn report_exit_error<E>(&self, err: &E) -> Result<()>
+    where
+        E: crate::ErrorTypeName + 'static,
+    {
+        self.post_error(EXIT_ERROR, err)
+    }
+
+    fn post_error<E>(&self, path: &str, err: &E) -> Result<()>
+    where
+        E: crate::ErrorTypeName + 'static,
+    {
+        let ty = err.error_type_name();
+        let message = crate::error::message(err);
+        let body = serde_json::to_vec(&ErrorReport::new(ty, &message)).map_err(Error::serialize)?;
+        let headers = Headers::new()
+            .with("lambda-extension-identifier", self.id.as_str())
+            .with("lambda-extension-function-error-type", ty);
+        http::api_request(&self.endpoint, "POST", path, &headers, &body)
+            .and_then(|(_, body)| http::drain(body))
+            .map_err(http::context("POST", path))
+    }
+}
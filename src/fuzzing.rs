@@ -0,0 +1,103 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! The HTTP client's parsers and encoders, for fuzz targets to exercise directly.
+//!
+//! This is available with the `fuzzing` feature, and isn't covered by semver; it changes
+//! whenever the client does. With [cargo-fuzz], a target can look like:
+//!
+//! ```rust,ignore
+//! #![no_main]
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+//!     if let Ok(response) = minlambda::fuzzing::read_response(data) {
+//!         let _ = std::io::copy(&mut response.into_body(), &mut std::io::sink());
+//!     }
+//! });
+//! ```
+//!
+//! [cargo-fuzz]: https://github.com/rust-fuzz/cargo-fuzz
+
+use crate::error::Result;
+use crate::http::{self, ChunkedWriter, Conn, Endpoint, Response};
+use crate::transport::{Addr, Connection};
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Splits a header line into its name and (trimmed) value, as response headers are.
+#[must_use]
+pub fn split_header(line: &str) -> Option<(&str, &str)> {
+    http::split_header(line)
+}
+
+/// Reads a response (the status line, headers, and a body, decoding chunked transfer encoding if
+/// the headers say to), as responses from the runtime API are read, from `input`.
+///
+/// # Errors
+///
+/// Returns an error if the response's head is malformed or too long. Errors in the body are
+/// returned when reading it.
+pub fn read_response(input: &[u8]) -> Result<Response> {
+    let (endpoint, conn) = memory(input, Arc::default());
+    http::read_response(&endpoint, conn, false)
+}
+
+/// Sends `writes` as a chunked request body, as streamed responses are, returning what would be
+/// sent over the connection (including the last chunk). Writes smaller than `chunk_size` are
+/// gathered into chunks of up to that size; larger ones are sent as chunks of their own.
+///
+/// # Errors
+///
+/// This doesn't fail in practice, since nothing is actually sent anywhere.
+pub fn write_chunked(writes: &[&[u8]], chunk_size: usize) -> io::Result<Vec<u8>> {
+    let output = Arc::default();
+    let (mut endpoint, conn) = memory(&[], Arc::clone(&output));
+    endpoint.chunk_size = chunk_size.max(1);
+    let mut stream = ChunkedWriter::new(&endpoint, conn);
+    for write in writes {
+        stream.write_all(write)?;
+    }
+    stream.finish()?.flush()?;
+    let output = output.lock().unwrap_or_else(PoisonError::into_inner);
+    Ok(output.clone())
+}
+
+/// Returns an endpoint that never keeps connections, and a connection that reads `input` and
+/// writes to `output`.
+fn memory(input: &[u8], output: Arc<Mutex<Vec<u8>>>) -> (Endpoint, Conn) {
+    let mut endpoint = Endpoint::new(Addr::Tcp(SocketAddr::from(([127, 0, 0, 1], 0))));
+    endpoint.keep_alive = false;
+    let stream = Memory {
+        input: Cursor::new(input.to_vec()),
+        output,
+    };
+    let conn = Conn::new(&endpoint, Box::new(stream));
+    (endpoint, conn)
+}
+
+/// An in-memory connection.
+#[derive(Debug)]
+struct Memory {
+    input: Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for Memory {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Memory {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+        output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Memory {}
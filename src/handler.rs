@@ -0,0 +1,112 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use serde::{Deserialize, Serialize};
+
+/// A handler for invocation events of type `D`.
+///
+/// Closures of the form `FnMut(D) -> Result<S, E>` are handlers, so most functions won't need to
+/// implement this trait. It's useful for library authors who want to ship reusable handler types,
+/// or for handlers that are structs holding state and configuration:
+///
+/// ```rust,no_run
+/// struct Greeter {
+///     greeting: String,
+/// }
+///
+/// impl minlambda::Handler<String> for Greeter {
+///     type Response = String;
+///     type Error = std::convert::Infallible;
+///
+///     fn handle(&mut self, name: String) -> Result<String, Self::Error> {
+///         Ok(format!("{}, {}!", self.greeting, name))
+///     }
+/// }
+///
+/// fn main() -> ! {
+///     minlambda::run(Greeter {
+///         greeting: String::from("Hello"),
+///     })
+/// }
+/// ```
+pub trait Handler<D> {
+    /// The response type, sent back to the runtime API as JSON.
+    ///
+    /// A [`NoResponse`](crate::NoResponse) response sends an empty body.
+    type Response: Serialize;
+    /// The error type, reported to the runtime API if handling an event fails.
+    type Error: crate::ErrorTypeName + 'static;
+
+    /// Handles an invocation event.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported to the runtime API as the invocation's error.
+    fn handle(&mut self, event: D) -> Result<Self::Response, Self::Error>;
+}
+
+impl<F, D, S, E> Handler<D> for F
+where
+    F: FnMut(D) -> Result<S, E>,
+    S: Serialize,
+    E: crate::ErrorTypeName + 'static,
+{
+    type Response = S;
+    type Error = E;
+
+    fn handle(&mut self, event: D) -> Result<S, E> {
+        self(event)
+    }
+}
+
+/// A handler for invocation events that borrow from the event body, for use with
+/// [`run_borrowed`](crate::run_borrowed).
+///
+/// Each event body is read into a buffer that is reused between invocations, and the event is
+/// deserialized from it with fields like `&str` (or `Cow<str>`, for strings that may contain
+/// escapes) pointing into the buffer rather than being copied out into new allocations.
+///
+/// ```rust,no_run
+/// use serde_derive::Deserialize;
+/// use std::borrow::Cow;
+///
+/// #[derive(Deserialize)]
+/// struct Request<'a> {
+///     #[serde(rename = "rawPath")]
+///     raw_path: &'a str,
+///     #[serde(borrow)]
+///     body: Option<Cow<'a, str>>,
+/// }
+///
+/// struct BodyLength;
+///
+/// impl minlambda::BorrowedHandler for BodyLength {
+///     type Event<'de> = Request<'de>;
+///     type Response = String;
+///     type Error = std::convert::Infallible;
+///
+///     fn handle(&mut self, event: Request<'_>) -> Result<String, Self::Error> {
+///         let len = event.body.map_or(0, |body| body.len());
+///         Ok(format!("{}: {} bytes", event.raw_path, len))
+///     }
+/// }
+///
+/// fn main() -> ! {
+///     minlambda::run_borrowed(BodyLength)
+/// }
+/// ```
+pub trait BorrowedHandler {
+    /// The event type, which may borrow from the event body.
+    type Event<'de>: Deserialize<'de>;
+    /// The response type, sent back to the runtime API as JSON.
+    type Response: Serialize;
+    /// The error type, reported to the runtime API if handling an event fails.
+    type Error: crate::ErrorTypeName + 'static;
+
+    /// Handles an invocation event.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported to the runtime API as the invocation's error.
+    fn handle(&mut self, event: Self::Event<'_>) -> Result<Self::Response, Self::Error>;
+}
@@ -1,191 +1,2099 @@
 // Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
 // SPDX-License-Identifier: MIT
 
-use serde::{de::DeserializeOwned, ser::SerializeStruct, Serialize, Serializer};
-use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
-use std::net::{SocketAddr, TcpStream};
+//! A minimal HTTP/1.1 client, for simple requests to servers other than the runtime API (such as
+//! webhooks, or the presigned URL a custom resource's response goes to) without pulling in a full
+//! HTTP stack.
+//!
+//! ```rust,no_run
+//! let response = minlambda::http::post("https://hooks.example.com/deploys")?
+//!     .header("content-type", "application/json")
+//!     .send(br#"{"status":"done"}"#)?;
+//! if response.status() >= 400 {
+//!     eprintln!("webhook failed: {}", std::io::read_to_string(response.into_body())?);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! This is the client minlambda talks to the runtime API with, and it implements the same subset
+//! of HTTP/1.1: there are no redirects, compression, or cookies, and request bodies are sent with
+//! a `Content-Length` or chunked. HTTPS URLs need the `tls` feature, which verifies servers'
+//! certificates against the Mozilla root certificates. Requests go through the proxy in
+//! `HTTPS_PROXY` or `HTTP_PROXY`, if one is set, unless the host is listed in `NO_PROXY`.
+//!
+//! Connections are kept open for reuse, one per thread, so a client should be created once (it's
+//! cheap to copy) rather than for each request.
 
-fn error(err: &str) -> Error {
-    Error::new(ErrorKind::InvalidData, err)
+use crate::base64;
+use crate::error::{Error, ErrorReport, Result};
+use crate::response;
+use crate::transport::{Addr, Stream, TcpOptions};
+use crate::{ClientContext, CognitoIdentity, Context, Deadline};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Where and how to connect to the runtime API.
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoint {
+    pub(crate) addr: Addr,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) tcp: TcpOptions,
+    /// How many times to try connecting before giving up; at least 1.
+    pub(crate) connect_attempts: u32,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    /// Whether to keep connections open for reuse by later requests.
+    pub(crate) keep_alive: bool,
+    /// Whether to request the next invocation as soon as a response or error has been sent.
+    pub(crate) pipeline: bool,
+    /// Whether to serialize responses and errors before sending them with a `Content-Length`,
+    /// rather than sending them with chunked transfer encoding as they're serialized.
+    pub(crate) buffered: bool,
+    pub(crate) user_agent: Arc<str>,
+    /// How many times to retry a request whose connection fails.
+    pub(crate) retries: u32,
+    /// How many times to retry a request that the runtime API responds to with 429, 500, or 503.
+    pub(crate) status_retries: u32,
+    /// The longest status or header line to accept in a response, in bytes.
+    pub(crate) max_header_line: usize,
+    /// The most header bytes to accept in a response, in total.
+    pub(crate) max_headers: usize,
+    /// The largest invocation event to accept, in bytes.
+    pub(crate) max_event: usize,
+    /// The capacities of the buffers for reading from and writing to connections, and the size
+    /// chunked request bodies are sent in; all at least 1.
+    pub(crate) read_buffer: usize,
+    pub(crate) write_buffer: usize,
+    pub(crate) chunk_size: usize,
 }
 
-pub(crate) fn get<D>(addr: SocketAddr, path: &str) -> Result<(String, D)>
-where
-    D: DeserializeOwned,
-{
-    let stream = http_start(addr, "GET", path, false)?;
-    let mut stream = BufReader::new(stream.into_inner()?);
-    check_response_code(&mut stream)?;
+/// The default `User-Agent` header of requests to the runtime API.
+pub(crate) const USER_AGENT: &str = concat!("minlambda/", env!("CARGO_PKG_VERSION"), " (rust)");
 
-    let mut buf = Vec::new();
-    let mut request_id = None;
-    let mut length = None;
-    stream.read_until(b'\n', &mut buf)?; // finish reading status line off the wire
-    loop {
-        buf.clear();
-        stream.read_until(b'\n', &mut buf)?;
-        if buf == b"\r\n" {
-            break;
+/// The default number of retries for requests the runtime API is too busy for.
+pub(crate) const STATUS_RETRIES: u32 = 3;
+
+/// The default longest header line to accept, which leaves room for as big a client context
+/// header as Lambda allows.
+pub(crate) const MAX_HEADER_LINE: usize = 8 * 1024;
+
+/// The default most header bytes to accept in total.
+pub(crate) const MAX_HEADERS: usize = 64 * 1024;
+
+/// The default largest invocation event to accept, which is more than Lambda sends.
+pub(crate) const MAX_EVENT: usize = 8 * 1024 * 1024;
+
+/// The default capacity of connection buffers, and size of chunks of request bodies.
+pub(crate) const BUFFER_SIZE: usize = 8 * 1024;
+
+/// The largest response Lambda accepts for an invocation (other than a streamed one), in bytes.
+const MAX_RESPONSE: usize = 6_291_556;
+
+/// The delay before the first retry of a request the runtime API is too busy for, doubling for
+/// each retry after that (with jitter).
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// The longest delay before retrying, even if the runtime API asks for a longer one.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The most connections to keep idle on a thread, each to a different address.
+const MAX_IDLE: usize = 4;
+
+thread_local! {
+    /// Connections left open by requests on this thread, at most one per address, for the next
+    /// request to the same address to reuse. Keying them by address keeps requests to other
+    /// servers (with [`Client`]) from closing the runtime API's connection.
+    static IDLE: RefCell<Vec<(Addr, Conn)>> = const { RefCell::new(Vec::new()) };
+
+    /// A connection on which the next invocation has already been requested, with the response
+    /// to that request still to be read.
+    static PIPELINED: RefCell<Option<(Addr, Conn)>> = const { RefCell::new(None) };
+
+    /// The buffer responses and errors are serialized into, if buffering them.
+    static BUFFER: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+
+    /// The buffer chunks of chunked request bodies are gathered in.
+    static CHUNK: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+
+    /// The buffer status, header, and chunk length lines of responses are read into.
+    static LINE: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+}
+
+impl Endpoint {
+    pub(crate) fn new(addr: Addr) -> Endpoint {
+        Endpoint {
+            addr,
+            connect_timeout: None,
+            tcp: TcpOptions::default(),
+            connect_attempts: 1,
+            read_timeout: None,
+            write_timeout: None,
+            keep_alive: true,
+            pipeline: false,
+            buffered: false,
+            user_agent: Arc::from(USER_AGENT),
+            retries: 0,
+            status_retries: STATUS_RETRIES,
+            max_header_line: MAX_HEADER_LINE,
+            max_headers: MAX_HEADERS,
+            max_event: MAX_EVENT,
+            read_buffer: BUFFER_SIZE,
+            write_buffer: BUFFER_SIZE,
+            chunk_size: BUFFER_SIZE,
         }
+    }
 
-        if let Some((name, value)) = std::str::from_utf8(&buf).ok().and_then(split_header) {
-            if request_id.is_none() && name.eq_ignore_ascii_case("Lambda-Runtime-Aws-Request-Id") {
-                request_id = Some(String::from(value));
+    fn connect(&self) -> Result<Conn> {
+        if self.keep_alive {
+            let idle = IDLE.with(|idle| {
+                let mut idle = idle.borrow_mut();
+                let index = idle.iter().position(|(addr, _)| *addr == self.addr)?;
+                Some(idle.remove(index).1)
+            });
+            if let Some(stream) = idle {
+                return Ok(stream);
+            }
+        }
+        let mut attempt = 1;
+        loop {
+            match self.open() {
+                Err(_) if attempt < self.connect_attempts => attempt += 1,
+                result => return Ok(Conn::new(self, result?)),
             }
-            if length.is_none() {
-                if name.eq_ignore_ascii_case("Transfer-Encoding") && value == "chunked" {
-                    length = Some(None);
-                } else if name.eq_ignore_ascii_case("Content-Length") {
-                    if let Ok(value) = value.parse() {
-                        length = Some(Some(value));
+        }
+    }
+
+    fn open(&self) -> io::Result<Stream> {
+        let stream = self.addr.connect(self.connect_timeout, self.tcp)?;
+        stream.set_timeouts(self.read_timeout, self.write_timeout)?;
+        Ok(stream)
+    }
+
+    /// Uses the first of `addrs` (such as the IPv6 and IPv4 addresses of a host name) that
+    /// accepts a connection, keeping that connection for the first request. If none do, the
+    /// first address is used anyway. Returns whether the choice is settled: there's only one
+    /// address, or one accepted a connection.
+    pub(crate) fn choose_addr(&mut self, addrs: &[Addr]) -> bool {
+        let [first, ..] = addrs else { return true };
+        if addrs.len() > 1 {
+            for addr in addrs {
+                self.addr = addr.clone();
+                if let Ok(stream) = self.open() {
+                    if self.keep_alive {
+                        keep_idle(addr.clone(), Conn::new(self, stream));
                     }
+                    return true;
+                }
+            }
+        }
+        self.addr = first.clone();
+        addrs.len() == 1
+    }
+
+    /// Makes a request, making it again on a new connection if it fails on a reused connection
+    /// (which the runtime API may have closed while it was idle), or if the connection breaks
+    /// partway through. Timeouts aren't retried, since a stale connection fails right away.
+    fn reconnecting<T>(&self, mut request: impl FnMut() -> Result<T>) -> Result<T> {
+        let reused = self.keep_alive
+            && IDLE.with(|idle| idle.borrow().iter().any(|(addr, _)| *addr == self.addr));
+        match request() {
+            Err(Error::Transport(err)) if reused && err.kind() != io::ErrorKind::TimedOut => {
+                request()
+            }
+            Err(Error::Transport(err)) if is_broken(&err) => request(),
+            result => result,
+        }
+    }
+
+    /// Makes a request like [`Endpoint::reconnecting`], making it again after a delay if the
+    /// connection fails, up to `retries` times, or if the runtime API responds that it's
+    /// overloaded or failed, up to `status_retries` times. The delay is from the `Retry-After`
+    /// header if there is one, and otherwise backs off exponentially.
+    fn retrying(&self, mut request: impl FnMut() -> Result<Response>) -> Result<(HeaderMap, Body)> {
+        let (mut retry, mut status_retry) = (0, 0);
+        loop {
+            let response = match self.reconnecting(&mut request) {
+                Err(Error::Transport(_)) if retry < self.retries => {
+                    std::thread::sleep(backoff(retry));
+                    retry += 1;
+                    continue;
                 }
+                response => response?,
+            };
+            if status_retry == self.status_retries || !retryable_status(response.status) {
+                return response.check();
             }
+            let delay = (response.headers.get("Retry-After"))
+                .and_then(|value| value.parse().ok())
+                .map_or_else(|| backoff(status_retry), Duration::from_secs);
+            drain(response.body).ok();
+            std::thread::sleep(delay.min(MAX_RETRY_DELAY));
+            status_retry += 1;
+        }
+    }
+
+    /// Keeps a connection whose response has been read in full for the next request.
+    fn release(&self, stream: Conn) {
+        // Anything already buffered would be mistaken for the start of the next response.
+        if self.keep_alive && stream.reader.buffer().is_empty() {
+            keep_idle(self.addr.clone(), stream);
         }
     }
 
-    let request_id = request_id.ok_or_else(|| error("missing request ID"))?;
-    let event = serde_json::from_reader(
-        match length.ok_or_else(|| error("can't determine body length"))? {
-            Some(remaining) => Body {
-                stream,
-                remaining,
-                chunked: false,
+    /// Requests the next invocation after a request to `path`, if it was an invocation's response
+    /// or error and pipelining is on, before the response to that request has been read. Returns
+    /// whether it did. (It doesn't if the next invocation has already been requested, such as by
+    /// an earlier attempt at the same request.)
+    fn pipeline_next(&self, stream: &mut impl Write, path: &str) -> Result<bool> {
+        let next = runtime("invocation/next");
+        let pipeline = self.pipeline
+            && self.keep_alive
+            && path.starts_with(&runtime("invocation/"))
+            && path != next
+            && PIPELINED.with(
+                |pipelined| !matches!(&*pipelined.borrow(), Some((addr, _)) if *addr == self.addr),
+            );
+        if pipeline {
+            write_head(stream, self, "GET", &next, &Headers::new())?;
+        }
+        Ok(pipeline)
+    }
+}
+
+/// A connection to the runtime API, with its buffers. These are kept along with a connection kept
+/// for reuse, so that requests on a reused connection don't allocate new ones.
+#[derive(Debug)]
+pub(crate) struct Conn {
+    reader: BufReader<Stream>,
+    /// What's been written and not sent yet.
+    writes: Vec<u8>,
+    write_buffer: usize,
+}
+
+impl Conn {
+    pub(crate) fn new(endpoint: &Endpoint, stream: Stream) -> Conn {
+        Conn {
+            reader: BufReader::with_capacity(endpoint.read_buffer, stream),
+            writes: Vec::with_capacity(endpoint.write_buffer),
+            write_buffer: endpoint.write_buffer,
+        }
+    }
+
+    fn send_writes(&mut self) -> io::Result<()> {
+        if !self.writes.is_empty() {
+            self.reader.get_mut().write_all(&self.writes)?;
+            self.writes.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl BufRead for Conn {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.writes.len() + buf.len() > self.write_buffer {
+            self.send_writes()?;
+        }
+        if buf.len() >= self.write_buffer {
+            self.reader.get_mut().write(buf)
+        } else {
+            self.writes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_writes()?;
+        self.reader.get_mut().flush()
+    }
+}
+
+/// Keeps `stream` idle for the next request to `addr`, replacing any connection to `addr` that
+/// already is, and closing the longest-idle connection if there are too many.
+fn keep_idle(addr: Addr, stream: Conn) {
+    IDLE.with(|idle| {
+        let mut idle = idle.borrow_mut();
+        idle.retain(|(idle, _)| *idle != addr);
+        if idle.len() == MAX_IDLE {
+            idle.remove(0);
+        }
+        idle.push((addr, stream));
+    });
+}
+
+/// Returns whether an error means the connection broke, rather than couldn't be made at all.
+fn is_broken(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Returns whether a response status says the runtime API is overloaded or failed, so the request
+/// is worth making again.
+pub(crate) fn retryable_status(status: u16) -> bool {
+    matches!(status, 429 | 500 | 503)
+}
+
+/// Returns a delay of between half and all of `RETRY_DELAY` doubled `retry` times (up to
+/// `MAX_RETRY_DELAY`), picked at random so that runtimes retrying at once don't all retry at once
+/// again.
+pub(crate) fn backoff(retry: u32) -> Duration {
+    let delay = (RETRY_DELAY.saturating_mul(1 << retry.min(16))).min(MAX_RETRY_DELAY);
+    let half = u64::try_from(delay.as_nanos() / 2).unwrap_or(u64::MAX);
+    let jitter = RandomState::new().hash_one(retry) % half;
+    delay / 2 + Duration::from_nanos(jitter)
+}
+
+/// The status line and headers of a response.
+struct Head {
+    status: u16,
+    headers: HeaderMap,
+    length: Length,
+    close: bool,
+}
+
+/// The headers of a response, looked up without regard to case.
+#[derive(Debug, Default)]
+pub(crate) struct HeaderMap(Vec<(String, String)>);
+
+impl HeaderMap {
+    /// Returns the value of the first header named `name`.
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// How the length of a response body is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Length {
+    Fixed(usize),
+    Chunked,
+    Unknown,
+}
+
+/// Reads the status line and headers of a response, skipping any interim (1xx) responses before
+/// it, such as `100 Continue`.
+fn read_head(endpoint: &Endpoint, stream: &mut impl BufRead) -> Result<Head> {
+    loop {
+        let (status, http_1_0) = with_line(|line| read_status(endpoint, stream, line))?;
+        let (headers, eof) = with_line(|line| read_headers(endpoint, stream, line))?;
+        // A `101 Switching Protocols` response is final; there's no HTTP/1.1 after it.
+        if (100..200).contains(&status) && status != 101 && !eof {
+            continue;
+        }
+
+        // Transfer codings are listed in the order they were applied, and chunked is always last.
+        let length = if (headers.get("Transfer-Encoding")).is_some_and(|value| {
+            (value.rsplit(',').next())
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+        }) {
+            Length::Chunked
+        } else if let Some(value) = headers.get("Content-Length") {
+            // A length that can't be parsed can't frame the body, so the response is unusable.
+            let len = Some(value.trim())
+                .filter(|len| !len.is_empty() && len.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|len| len.parse().ok())
+                .ok_or_else(|| Error::protocol("invalid Content-Length"))?;
+            Length::Fixed(len)
+        } else {
+            Length::Unknown
+        };
+        // HTTP/1.0 connections close after each response.
+        let close = eof
+            || http_1_0
+            || (headers.get("Connection")).is_some_and(|value| value.eq_ignore_ascii_case("close"));
+        return Ok(Head {
+            status,
+            headers,
+            length,
+            close,
+        });
+    }
+}
+
+/// Reads the status line of a response, returning the status code and whether the response is
+/// HTTP/1.0 (as some emulators and test servers send) rather than HTTP/1.1.
+fn read_status(
+    endpoint: &Endpoint,
+    stream: &mut impl BufRead,
+    line: &mut Vec<u8>,
+) -> Result<(u16, bool)> {
+    if read_line(stream, line, endpoint.max_header_line)? == 0 {
+        return Err(Error::Transport(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before response",
+        )));
+    }
+    if debug() {
+        eprintln!(
+            "{DEBUG_PREFIX} < {}",
+            String::from_utf8_lossy(line).trim_end()
+        );
+    }
+    let http_1_0 = line.starts_with(b"HTTP/1.0 ");
+    let status = (line
+        .strip_prefix(b"HTTP/1.1 ")
+        .or_else(|| line.strip_prefix(b"HTTP/1.0 ")))
+    .and_then(|rest| rest.get(..3))
+    .and_then(|status| std::str::from_utf8(status).ok())
+    .and_then(|status| status.parse::<u16>().ok())
+    .ok_or_else(|| Error::protocol("malformed HTTP response"))?;
+    Ok((status, http_1_0))
+}
+
+/// Reads the headers (or trailers) of a response, returning them and whether the connection was
+/// closed before the end of them.
+fn read_headers(
+    endpoint: &Endpoint,
+    stream: &mut impl BufRead,
+    line: &mut Vec<u8>,
+) -> Result<(HeaderMap, bool)> {
+    let mut headers = Vec::new();
+    let mut total = 0;
+    let mut eof = false;
+    loop {
+        line.clear();
+        total += read_line(stream, line, endpoint.max_header_line)?;
+        if total > endpoint.max_headers {
+            return Err(Error::protocol("response headers too long"));
+        }
+        if line.is_empty() {
+            eof = true;
+            break;
+        }
+        if line == b"\r\n" {
+            break;
+        }
+        if let Some((name, value)) = std::str::from_utf8(line).ok().and_then(split_header) {
+            debug_header('<', name, value);
+            headers.push((String::from(name), String::from(value)));
+        }
+    }
+    Ok((HeaderMap(headers), eof))
+}
+
+impl Head {
+    fn body(&self, endpoint: Endpoint, stream: Conn) -> Body {
+        Body {
+            endpoint,
+            stream: Some(stream),
+            remaining: match self.length {
+                Length::Fixed(len) => len,
+                Length::Chunked | Length::Unknown => 0,
             },
-            None => Body {
-                stream,
-                remaining: 0,
-                chunked: true,
+            chunked: self.length == Length::Chunked,
+            read: 0,
+            limit: usize::MAX,
+            // HTTP/1.1 says a body without a length lasts until the connection is closed; some
+            // emulators send events like that.
+            until_close: self.length == Length::Unknown,
+            done: self.length == Length::Fixed(0),
+            reuse: if self.close || self.length == Length::Unknown {
+                Reuse::Close
+            } else {
+                Reuse::Idle
             },
-        },
-    )?;
-    Ok((request_id, event))
+        }
+    }
+}
+
+pub(crate) fn get_invocation(endpoint: &Endpoint, path: &str) -> Result<(Context, Body)> {
+    if path == "invocation/next" {
+        let pipelined = PIPELINED.with(|pipelined| {
+            let mut pipelined = pipelined.borrow_mut();
+            pipelined.take_if(|(addr, _)| *addr == endpoint.addr)
+        });
+        if let Some((_, stream)) = pipelined {
+            // If the connection was closed before the runtime API answered, ask again.
+            match read_invocation(endpoint, stream) {
+                Err(Error::Transport(err)) if err.kind() != io::ErrorKind::TimedOut => {}
+                result => return result,
+            }
+        }
+    }
+    endpoint
+        .reconnecting(|| get_once(endpoint, path))
+        .map_err(context("GET", path))
+}
+
+fn get_once(endpoint: &Endpoint, path: &str) -> Result<(Context, Body)> {
+    let stream = http_start(endpoint, "GET", &runtime(path), &Headers::new())?;
+    read_invocation(endpoint, send(stream)?)
+}
+
+fn read_invocation(endpoint: &Endpoint, stream: Conn) -> Result<(Context, Body)> {
+    let (headers, mut body) = read_response(endpoint, stream, false)?.check()?;
+    body.limit = endpoint.max_event;
+    let context = Context {
+        request_id: (headers.get("Lambda-Runtime-Aws-Request-Id"))
+            .map(String::from)
+            .ok_or_else(|| Error::protocol("missing request ID"))?,
+        // Only the request ID is needed to respond to the invocation, so the other headers fall
+        // back to defaults rather than failing it.
+        deadline: (headers.get("Lambda-Runtime-Deadline-Ms"))
+            .and_then(|value| value.trim().parse().ok())
+            .map_or_else(Deadline::longest, Deadline::from_unix_millis),
+        invoked_function_arn: (headers.get("Lambda-Runtime-Invoked-Function-Arn"))
+            .map(String::from)
+            .unwrap_or_default(),
+        trace_id: headers.get("Lambda-Runtime-Trace-Id").map(String::from),
+        client_context: (headers.get("Lambda-Runtime-Client-Context"))
+            .and_then(ClientContext::from_header),
+        identity: (headers.get("Lambda-Runtime-Cognito-Identity"))
+            .and_then(CognitoIdentity::from_header),
+    };
+    Ok((context, body))
+}
+
+/// Posts an invocation's response, failing with [`Error::ResponseTooLarge`] instead if it's
+/// larger than Lambda accepts. (Unless responses are buffered, some of it will have been sent
+/// by then, but the request is abandoned before it's complete.)
+pub(crate) fn post_response<S>(endpoint: &Endpoint, path: &str, body: &S) -> Result<()>
+where
+    S: Serialize,
+{
+    post_with_headers(endpoint, path, &Headers::new(), body, MAX_RESPONSE)
+        .map_err(context("POST", path))
+}
+
+fn post_with_headers<S>(
+    endpoint: &Endpoint,
+    path: &str,
+    headers: &Headers<'_>,
+    body: &S,
+    limit: usize,
+) -> Result<()>
+where
+    S: Serialize,
+{
+    if !endpoint.buffered {
+        return (endpoint.retrying(|| post_once(endpoint, path, headers, body, limit)))
+            .and_then(|(_, body)| drain(body));
+    }
+    let mut buf = BUFFER.take();
+    buf.clear();
+    let result = if response::is_empty(body) {
+        Ok(())
+    } else {
+        serde_json::to_writer(LimitedWriter::new(&mut buf, limit), body).map_err(Error::serialize)
+    };
+    let result = result
+        .and_then(|()| api_request(endpoint, "POST", &runtime(path), headers, &buf))
+        .and_then(|(_, body)| drain(body));
+    BUFFER.set(buf);
+    result
+}
+
+fn post_once<S>(
+    endpoint: &Endpoint,
+    path: &str,
+    headers: &Headers<'_>,
+    body: &S,
+    limit: usize,
+) -> Result<Response>
+where
+    S: Serialize,
+{
+    let path = runtime(path);
+    let headers = headers.clone().with("transfer-encoding", "chunked");
+    let mut stream = ChunkedWriter::new(endpoint, http_start(endpoint, "POST", &path, &headers)?);
+    if !response::is_empty(body) {
+        serde_json::to_writer(LimitedWriter::new(&mut stream, limit), body)
+            .map_err(Error::serialize)?;
+    }
+    let mut stream = stream.finish()?;
+    let pipelined = endpoint.pipeline_next(&mut stream, &path)?;
+    read_response(endpoint, send(stream)?, pipelined)
+}
+
+/// Posts an invocation's response as raw bytes, failing with [`Error::ResponseTooLarge`] instead
+/// if it's larger than Lambda accepts.
+pub(crate) fn post_raw_response(
+    endpoint: &Endpoint,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    if body.len() > MAX_RESPONSE {
+        return Err(Error::ResponseTooLarge {
+            limit: MAX_RESPONSE,
+        });
+    }
+    let headers = Headers::new().with("content-type", content_type);
+    api_request(endpoint, "POST", &runtime(path), &headers, body)
+        .and_then(|(_, body)| drain(body))
+        .map_err(context("POST", path))
+}
+
+/// Makes a request to `path` on the endpoint, which can be any of the APIs it serves (such as
+/// `/2020-01-01/extension/register`) and not just the runtime API, sending `body` with a
+/// `Content-Length`. Returns the response headers and body, or an error for error statuses (after
+/// retrying those that might not happen again).
+pub(crate) fn api_request(
+    endpoint: &Endpoint,
+    method: &str,
+    path: &str,
+    headers: &Headers<'_>,
+    body: &[u8],
+) -> Result<(HeaderMap, Body)> {
+    let headers = headers
+        .clone()
+        .with("content-length", body.len().to_string());
+    endpoint.retrying(|| request_once(endpoint, method, path, &headers, body))
+}
+
+/// Makes a request once, with `headers` including the `Content-Length`.
+fn request_once(
+    endpoint: &Endpoint,
+    method: &str,
+    path: &str,
+    headers: &Headers<'_>,
+    body: &[u8],
+) -> Result<Response> {
+    let mut stream = http_start(endpoint, method, path, headers)?;
+    stream.write_all(body)?;
+    let pipelined = endpoint.pipeline_next(&mut stream, path)?;
+    read_response(endpoint, send(stream)?, pipelined)
+}
+
+/// Returns the path of a runtime API resource.
+pub(crate) fn runtime(path: &str) -> String {
+    format!("/2018-06-01/runtime/{path}")
+}
+
+/// Returns a function adding what a request to `path` was for to its transport errors. The path
+/// is a runtime API resource if it's relative, like the paths [`runtime`] takes, and any path on
+/// the endpoint otherwise.
+pub(crate) fn context<'a>(method: &'a str, path: &'a str) -> impl FnOnce(Error) -> Error + 'a {
+    move |err| err.context(|| describe(method, path))
+}
+
+/// Describes a request for error messages, like "while posting the response for request abc-123
+/// (POST /2018-06-01/runtime/invocation/abc-123/response)".
+fn describe(method: &str, path: &str) -> String {
+    let path = if path.starts_with('/') {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(runtime(path))
+    };
+    let parts = path.split('/').skip(1).collect::<Vec<_>>();
+    let what = match parts.as_slice() {
+        ["2018-06-01", "runtime", "invocation", "next"] => {
+            Cow::Borrowed("waiting for the next invocation")
+        }
+        ["2018-06-01", "runtime", "invocation", id, "response"] => {
+            format!("posting the response for request {id}").into()
+        }
+        ["2018-06-01", "runtime", "invocation", id, "error"] => {
+            format!("posting the error for request {id}").into()
+        }
+        ["2018-06-01", "runtime", "init", "error"] => "reporting an initialization error".into(),
+        ["2018-06-01", "runtime", "restore", "next"] => {
+            "waiting for the snapshot to be restored".into()
+        }
+        ["2018-06-01", "runtime", "restore", "error"] => "reporting a restore error".into(),
+        ["2020-01-01", "extension", "register"] => "registering the extension".into(),
+        ["2020-01-01", "extension", "event", "next"] => {
+            "waiting for the next extension event".into()
+        }
+        ["2020-01-01", "extension", "init" | "exit", "error"] => {
+            "reporting an extension error".into()
+        }
+        ["2022-07-01", "telemetry"] => "subscribing to the Telemetry API".into(),
+        ["2020-08-15", "logs"] => "subscribing to the Logs API".into(),
+        _ => "making a request".into(),
+    };
+    format!("while {what} ({method} {path})")
+}
+
+/// Returns a function adding which request to which server failed to a [`Client`]'s transport
+/// errors, like "while requesting GET /items from example.com".
+fn client_context<'a>(
+    endpoint: &'a Endpoint,
+    method: &'a str,
+    path: &'a str,
+) -> impl FnOnce(Error) -> Error + 'a {
+    move |err| err.context(|| format!("while requesting {method} {path} from {}", endpoint.addr))
 }
 
-pub(crate) fn post<S>(addr: SocketAddr, path: &str, body: &S) -> Result<()>
+pub(crate) fn post_error(endpoint: &Endpoint, path: &str, ty: &str, err: &str) -> Result<()> {
+    post_error_body(endpoint, path, ty, &ErrorReport::new(ty, err))
+}
+
+/// Posts an error, with its type in the `Lambda-Runtime-Function-Error-Type` header as well as
+/// (usually) the body.
+pub(crate) fn post_error_body<S>(endpoint: &Endpoint, path: &str, ty: &str, body: &S) -> Result<()>
 where
     S: Serialize,
 {
-    let mut stream = ChunkedWriter(http_start(addr, "POST", path, true)?);
-    serde_json::to_writer(&mut stream, body)?;
-    check_response_code(&mut stream.finish()?.into_inner()?)
+    let headers = Headers::new().with("lambda-runtime-function-error-type", ty);
+    post_with_headers(endpoint, path, &headers, body, usize::MAX).map_err(context("POST", path))
 }
 
-pub(crate) fn post_error(addr: SocketAddr, path: &str, ty: &'static str, err: &str) -> Result<()> {
-    let stream = ChunkedWriter(http_start(addr, "POST", path, true)?);
-    let mut writer = serde_json::Serializer::new(stream);
+/// Extra headers for a request, besides `Host` and `User-Agent`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Headers<'a>(Vec<(Cow<'a, str>, Cow<'a, str>)>);
+
+impl<'a> Headers<'a> {
+    pub(crate) fn new() -> Headers<'a> {
+        Headers::default()
+    }
 
-    let mut s = writer.serialize_struct("Error", 2)?;
-    s.serialize_field("errorType", ty)?;
-    s.serialize_field("errorMessage", err)?;
-    s.end()?;
+    /// Adds a header. Any line breaks in the name or value, which would end the header early, are
+    /// replaced with spaces.
+    #[must_use]
+    pub(crate) fn with<V: Into<Cow<'a, str>>>(mut self, name: &'a str, value: V) -> Headers<'a> {
+        self.0.push((one_line(name.into()), one_line(value.into())));
+        self
+    }
+}
 
-    check_response_code(&mut writer.into_inner().finish()?.into_inner()?)
+fn one_line(value: Cow<'_, str>) -> Cow<'_, str> {
+    if value.contains(['\r', '\n']) {
+        Cow::Owned(value.replace(['\r', '\n'], " "))
+    } else {
+        value
+    }
 }
 
 fn http_start(
-    addr: SocketAddr,
+    endpoint: &Endpoint,
     method: &str,
     path: &str,
-    chunked: bool,
-) -> Result<BufWriter<TcpStream>> {
-    let mut stream = BufWriter::new(TcpStream::connect(addr)?);
+    headers: &Headers<'_>,
+) -> Result<Conn> {
+    let mut stream = endpoint.connect()?;
+    write_head(&mut stream, endpoint, method, path, headers)?;
+    Ok(stream)
+}
+
+fn write_head(
+    stream: &mut impl Write,
+    endpoint: &Endpoint,
+    method: &str,
+    path: &str,
+    headers: &Headers<'_>,
+) -> Result<()> {
+    // A proxy forwarding the request needs the whole URL.
+    let proxy = endpoint.addr.forwarding_proxy();
+    let target = match proxy {
+        Some(_) => Cow::Owned(format!("http://{}{path}", endpoint.addr)),
+        None => Cow::Borrowed(path),
+    };
     write!(
         stream,
-        "{} /2018-06-01/runtime/{} HTTP/1.1\r\nhost: {}\r\n{}\r\n",
-        method,
-        path,
-        addr,
-        if chunked {
-            "transfer-encoding: chunked\r\n"
-        } else {
-            ""
-        },
+        "{method} {target} HTTP/1.1\r\nhost: {}\r\nuser-agent: {}\r\n",
+        endpoint.addr, endpoint.user_agent,
     )?;
+    let auth = proxy.and_then(|proxy| proxy.auth());
+    if let Some(auth) = auth {
+        write!(stream, "proxy-authorization: {auth}\r\n")?;
+    }
+    for (name, value) in &headers.0 {
+        write!(stream, "{name}: {value}\r\n")?;
+    }
+    stream.write_all(b"\r\n")?;
+    if debug() {
+        eprintln!("{DEBUG_PREFIX} > {method} {target} HTTP/1.1");
+        debug_header('>', "host", &endpoint.addr.to_string());
+        debug_header('>', "user-agent", &endpoint.user_agent);
+        if let Some(auth) = auth {
+            debug_header('>', "proxy-authorization", auth);
+        }
+        for (name, value) in &headers.0 {
+            debug_header('>', name, value);
+        }
+    }
+    Ok(())
+}
+
+/// What lines logged for `MINLAMBDA_DEBUG_HTTP` start with.
+const DEBUG_PREFIX: &str = "minlambda http:";
+
+/// Returns whether to log the request lines, status lines, and headers of requests and responses
+/// (but not their bodies) to stderr, which the `MINLAMBDA_DEBUG_HTTP=1` environment variable turns
+/// on for diagnosing problems with emulators or the runtime API.
+fn debug() -> bool {
+    static DEBUG: OnceLock<bool> = OnceLock::new();
+    *DEBUG
+        .get_or_init(|| std::env::var_os("MINLAMBDA_DEBUG_HTTP").is_some_and(|value| value == "1"))
+}
+
+/// Logs a header being sent (`>`) or received (`<`), if debugging, with the values of those that
+/// carry credentials left out.
+fn debug_header(direction: char, name: &str, value: &str) {
+    if debug() {
+        let secret = [
+            "authorization",
+            "proxy-authorization",
+            "cookie",
+            "set-cookie",
+        ]
+        .iter()
+        .any(|secret| name.eq_ignore_ascii_case(secret));
+        let value = if secret { "[redacted]" } else { value };
+        eprintln!("{DEBUG_PREFIX} {direction} {name}: {value}");
+    }
+}
+
+/// Sends what's been written to a connection, returning it for reading the response.
+fn send(mut stream: Conn) -> Result<Conn> {
+    stream.flush()?;
     Ok(stream)
 }
 
-fn check_response_code(mut stream: impl Read) -> Result<()> {
-    let mut buf = [0; 12];
-    stream.read_exact(&mut buf)?;
+/// The most of an error response body to keep.
+const MAX_ERROR_BODY: u64 = 64 * 1024;
 
-    if &buf[0..9] == b"HTTP/1.1 " {
-        if let Some(status) = std::str::from_utf8(&buf[9..12])
-            .ok()
-            .and_then(|s| s.parse::<u16>().ok())
-        {
-            return if status >= 400 {
-                Err(error(&format!("received HTTP error code {}", status)))
-            } else {
-                Ok(())
-            };
+/// The most of a successful response body to read and discard; if there's more, the connection is
+/// closed instead of being reused.
+const MAX_DRAIN: u64 = 64 * 1024;
+
+/// A response to a request made with a [`Client`], whatever its status.
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    headers: HeaderMap,
+    body: Body,
+}
+
+impl Response {
+    /// Returns the status code.
+    #[must_use]
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Returns the value of the first header named `name`, ignoring case.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the headers, in the order they were sent.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        (self.headers.0.iter()).map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Returns the body, for reading.
+    ///
+    /// The connection is kept for reuse if the body is read to the end.
+    #[must_use]
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Returns an error (with the response body) for error statuses, and otherwise the headers
+    /// and body.
+    fn check(self) -> Result<(HeaderMap, Body)> {
+        if self.status >= 400 {
+            return Err(error_response(self.status, self.body));
         }
+        Ok((self.headers, self.body))
+    }
+}
+
+/// Reads the response to a request.
+///
+/// If the next invocation was `pipelined` after the request, the connection is kept for reading
+/// its response once the body has been read, rather than for the next request.
+pub(crate) fn read_response(
+    endpoint: &Endpoint,
+    mut stream: Conn,
+    pipelined: bool,
+) -> Result<Response> {
+    let head = read_head(endpoint, &mut stream)?;
+    let mut body = head.body(endpoint.clone(), stream);
+    if pipelined && body.reuse == Reuse::Idle {
+        body.reuse = Reuse::Pipelined;
+    }
+    Ok(Response {
+        status: head.status,
+        headers: head.headers,
+        body,
+    })
+}
+
+/// Reads the response to a request, returning an error (with the response body) for error
+/// statuses. Otherwise, the body is discarded.
+fn check_response_code(endpoint: &Endpoint, stream: Conn, pipelined: bool) -> Result<()> {
+    drain(read_response(endpoint, stream, pipelined)?.check()?.1)
+}
+
+/// Reads and discards a response body so the connection can be reused.
+pub(crate) fn drain(mut body: Body) -> Result<()> {
+    // A body without a length lasts until the connection is closed, so it's not worth waiting
+    // for; the connection can't be reused anyway.
+    if !body.until_close {
+        io::copy(&mut body.by_ref().take(MAX_DRAIN), &mut io::sink())?;
     }
+    Ok(())
+}
+
+/// Reads what's worth keeping of an error response's body.
+fn error_response(status: u16, body: Body) -> Error {
+    let mut buf = Vec::new();
+    if let Err(err) = body.take(MAX_ERROR_BODY).read_to_end(&mut buf) {
+        return err.into();
+    }
+    Error::RuntimeApi {
+        status,
+        body: String::from_utf8_lossy(&buf).into_owned(),
+    }
+}
+
+/// Calls `f` with the (cleared) buffer for reading lines into.
+fn with_line<T>(f: impl FnOnce(&mut Vec<u8>) -> T) -> T {
+    let mut line = LINE.take();
+    line.clear();
+    let result = f(&mut line);
+    LINE.set(line);
+    result
+}
 
-    Err(error("malformed HTTP response"))
+/// Reads a line into `line`, like [`BufRead::read_until`], failing if it's longer than `max`
+/// bytes.
+fn read_line(stream: &mut impl BufRead, line: &mut Vec<u8>, max: usize) -> Result<usize> {
+    let limit = u64::try_from(max).map_or(u64::MAX, |max| max.saturating_add(1));
+    let len = stream.take(limit).read_until(b'\n', line)?;
+    if len > max {
+        return Err(Error::protocol("response line too long"));
+    }
+    Ok(len)
 }
 
-fn split_header(buf: &str) -> Option<(&str, &str)> {
+pub(crate) fn split_header(buf: &str) -> Option<(&str, &str)> {
     let mut iter = buf.splitn(2, ':');
     Some((iter.next()?, iter.next()?.trim()))
 }
 
-struct Body {
-    stream: BufReader<TcpStream>,
+/// The body of an invocation event, as read from the runtime API, or of a [`Response`].
+///
+/// This implements [`Read`], decoding chunked transfer encoding if the server uses it.
+#[derive(Debug)]
+pub struct Body {
+    endpoint: Endpoint,
+    /// Only `None` once the connection has been released.
+    stream: Option<Conn>,
     remaining: usize,
     chunked: bool,
+    /// How much of the body has been read, and how much can be.
+    read: usize,
+    limit: usize,
+    /// Whether the body is read until the connection is closed, when there's no length.
+    until_close: bool,
+    done: bool,
+    /// What to do with the connection once the body has been read.
+    reuse: Reuse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reuse {
+    /// Close it.
+    Close,
+    /// Keep it for the next request.
+    Idle,
+    /// Keep it for reading the response to the pipelined request for the next invocation.
+    Pipelined,
 }
 
 impl Read for Body {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if self.chunked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.read_framed(buf)?;
+        self.read += count;
+        // What's left of a body with a length (or of the current chunk) counts too, so that an
+        // oversized body fails before much of it is read.
+        if self.read.saturating_add(self.remaining) > self.limit {
+            return Err(Error::PayloadTooLarge { limit: self.limit }.into());
+        }
+        Ok(count)
+    }
+}
+
+impl Body {
+    /// Reads from the body, decoding chunked transfer encoding if it's used.
+    fn read_framed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let stream = match &mut self.stream {
+            Some(stream) if !self.done => stream,
+            _ => return Ok(0),
+        };
+        if self.until_close {
+            let count = stream.read(buf)?;
+            if count == 0 && !buf.is_empty() {
+                self.done = true;
+            }
+            Ok(count)
+        } else if self.chunked {
             if self.remaining == 0 {
-                let mut len = String::new();
-                self.stream.read_line(&mut len)?;
-                self.remaining = usize::from_str_radix(len.trim(), 16)
-                    .map_err(|_| error("invalid chunk length"))?;
+                let endpoint = &self.endpoint;
+                self.remaining = with_line(|len| {
+                    read_line(stream, len, endpoint.max_header_line)?;
+                    // Any chunk extensions, after a semicolon, are ignored.
+                    (std::str::from_utf8(len).ok())
+                        .and_then(|len| len.split(';').next())
+                        .map(str::trim)
+                        .filter(|len| !len.is_empty() && len.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .and_then(|len| usize::from_str_radix(len, 16).ok())
+                        .ok_or_else(|| Error::protocol("invalid chunk length"))
+                })?;
                 if self.remaining == 0 {
+                    // read out any trailers, up to the blank line that ends the body
+                    with_line(|line| read_headers(endpoint, stream, line))?;
+                    self.done = true;
                     return Ok(0);
                 }
             }
 
             let len = buf.len().min(self.remaining);
-            let count = self.stream.read(&mut buf[..len])?;
+            let count = read_some(stream, &mut buf[..len])?;
             self.remaining -= count;
             if self.remaining == 0 {
-                // read out the CRLF
-                self.stream.read_exact(&mut [0; 2])?;
+                let mut crlf = [0; 2];
+                stream.read_exact(&mut crlf)?;
+                if crlf != *b"\r\n" {
+                    return Err(Error::protocol("missing CRLF after chunk").into());
+                }
             }
             Ok(count)
-        } else if self.remaining == 0 {
-            Ok(0)
         } else {
             let len = buf.len().min(self.remaining);
-            let count = self.stream.read(&mut buf[..len])?;
+            let count = read_some(stream, &mut buf[..len])?;
             self.remaining -= count;
+            if self.remaining == 0 {
+                self.done = true;
+            }
             Ok(count)
         }
     }
 }
 
-struct ChunkedWriter(BufWriter<TcpStream>);
+/// Reads into `buf` from a body that has at least that much left, failing if the connection
+/// closes first rather than letting the body look complete.
+fn read_some(stream: &mut Conn, buf: &mut [u8]) -> io::Result<usize> {
+    let count = stream.read(buf)?;
+    if count == 0 && !buf.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before end of body",
+        ));
+    }
+    Ok(count)
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            match self.reuse {
+                _ if !self.done => {}
+                Reuse::Close => {}
+                Reuse::Idle => self.endpoint.release(stream),
+                Reuse::Pipelined => {
+                    let addr = self.endpoint.addr.clone();
+                    PIPELINED.with(|pipelined| *pipelined.borrow_mut() = Some((addr, stream)));
+                }
+            }
+        }
+    }
+}
+
+/// A writer for streaming a response body to the runtime API.
+///
+/// The request to the runtime API is not started until the first write, so a handler that fails
+/// before writing anything can still have its error reported normally. Once started, the response
+/// is sent in [streaming mode][streaming], and a failure part-way through is reported with error
+/// trailers.
+///
+/// [streaming]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-custom.html#runtimes-custom-response-streaming
+#[derive(Debug)]
+pub struct ResponseWriter {
+    endpoint: Endpoint,
+    path: String,
+    stream: Option<ChunkedWriter>,
+}
+
+impl ResponseWriter {
+    pub(crate) fn new(endpoint: Endpoint, path: &str) -> ResponseWriter {
+        ResponseWriter {
+            endpoint,
+            path: runtime(path),
+            stream: None,
+        }
+    }
+
+    /// Finishes the response, waiting for the runtime API to accept it. If nothing was written,
+    /// an empty response is sent.
+    ///
+    /// A `ResponseWriter` that is dropped without being finished abandons the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn finish(mut self) -> Result<()> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.start()?,
+        };
+        (stream.finish().map_err(Error::transport))
+            .and_then(send)
+            .and_then(|stream| check_response_code(&self.endpoint, stream, false))
+            .map_err(context("POST", &self.path))
+    }
+
+    /// Ends a started response with error trailers. Returns `false` if the response hasn't
+    /// started, in which case the error should be reported to the error endpoint instead.
+    pub(crate) fn fail(mut self, ty: &str, err: &str) -> Result<bool> {
+        let path = std::mem::take(&mut self.path);
+        self.end_with_error(ty, err).map_err(context("POST", &path))
+    }
+
+    fn end_with_error(self, ty: &str, err: &str) -> Result<bool> {
+        let mut stream = match self.stream {
+            Some(stream) => stream.end()?,
+            None => return Ok(false),
+        };
+        let body = serde_json::to_string(&ErrorReport::new(ty, err)).map_err(Error::serialize)?;
+        write!(
+            stream,
+            "0\r\nLambda-Runtime-Function-Error-Type: {}\r\n\
+             Lambda-Runtime-Function-Error-Body: {}\r\n\r\n",
+            one_line(ty.into()),
+            base64::encode(body.as_bytes()),
+        )?;
+        check_response_code(&self.endpoint, send(stream)?, false)?;
+        Ok(true)
+    }
+
+    fn start(&self) -> Result<ChunkedWriter> {
+        let headers = Headers::new()
+            .with("transfer-encoding", "chunked")
+            .with("lambda-runtime-function-response-mode", "streaming")
+            .with(
+                "trailer",
+                "Lambda-Runtime-Function-Error-Type, Lambda-Runtime-Function-Error-Body",
+            );
+        let stream = http_start(&self.endpoint, "POST", &self.path, &headers)
+            .map_err(context("POST", &self.path))?;
+        Ok(ChunkedWriter::new(&self.endpoint, stream))
+    }
+
+    fn stream(&mut self) -> Result<&mut ChunkedWriter> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.start()?,
+        };
+        Ok(self.stream.insert(stream))
+    }
+}
+
+impl Write for ResponseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A writer that fails with [`Error::ResponseTooLarge`] once more than `limit` bytes have been
+/// written to it.
+#[derive(Debug)]
+struct LimitedWriter<W> {
+    inner: W,
+    limit: usize,
+    written: usize,
+}
+
+impl<W> LimitedWriter<W> {
+    fn new(inner: W, limit: usize) -> LimitedWriter<W> {
+        LimitedWriter {
+            inner,
+            limit,
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written.saturating_add(buf.len()) > self.limit {
+            return Err(Error::ResponseTooLarge { limit: self.limit }.into());
+        }
+        let count = self.inner.write(buf)?;
+        self.written += count;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that sends what's written to it as chunks of (up to) `chunk_size`, rather than
+/// whatever size the writes are.
+#[derive(Debug)]
+pub(crate) struct ChunkedWriter {
+    stream: Conn,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+}
 
 impl ChunkedWriter {
-    pub(crate) fn finish(mut self) -> Result<BufWriter<TcpStream>> {
-        self.0.write_all(b"0\r\n\r\n")?;
-        Ok(self.0)
+    pub(crate) fn new(endpoint: &Endpoint, stream: Conn) -> ChunkedWriter {
+        let mut chunk = CHUNK.take();
+        chunk.clear();
+        chunk.reserve(endpoint.chunk_size);
+        ChunkedWriter {
+            stream,
+            chunk,
+            chunk_size: endpoint.chunk_size,
+        }
+    }
+
+    /// Sends the last chunk, returning the stream for ending the body.
+    fn end(mut self) -> io::Result<Conn> {
+        self.send()?;
+        CHUNK.set(self.chunk);
+        Ok(self.stream)
+    }
+
+    pub(crate) fn finish(self) -> io::Result<Conn> {
+        let mut stream = self.end()?;
+        stream.write_all(b"0\r\n\r\n")?;
+        Ok(stream)
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        // a zero-length chunk would end the body
+        if !self.chunk.is_empty() {
+            write_chunk(&mut self.stream, &self.chunk)?;
+            self.chunk.clear();
+        }
+        Ok(())
     }
 }
 
+fn write_chunk(stream: &mut impl Write, chunk: &[u8]) -> io::Result<()> {
+    write!(stream, "{:x}\r\n", chunk.len())?;
+    stream.write_all(chunk)?;
+    stream.write_all(b"\r\n")
+}
+
 impl Write for ChunkedWriter {
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        write!(self.0, "{:x}\r\n", buf.len())?;
-        self.0.write_all(buf)?;
-        self.0.write_all(b"\r\n")?;
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.chunk.len() + buf.len() > self.chunk_size {
+            self.send()?;
+        }
+        if buf.len() >= self.chunk_size {
+            write_chunk(&mut self.stream, buf)?;
+        } else {
+            self.chunk.extend_from_slice(buf);
+        }
         Ok(buf.len())
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.0.flush()
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()?;
+        self.stream.flush()
+    }
+}
+
+/// A client for one HTTP server. See the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Client {
+    endpoint: Endpoint,
+    /// The server's addresses, to choose between on the first request.
+    addrs: Arc<[Addr]>,
+    /// The address chosen, shared between clones once it is.
+    chosen: Arc<OnceLock<Addr>>,
+}
+
+impl Client {
+    /// Creates a client for the plain HTTP server at `addr`.
+    #[must_use]
+    pub fn new(addr: SocketAddr) -> Client {
+        Client::with_addrs(&[Addr::Tcp(addr)])
+    }
+
+    /// Creates a client for the server of an `http://` or `https://` URL. Any path in the URL is
+    /// ignored; requests are made to paths passed to [`Client::get`] and the like.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL isn't an HTTP URL, the host (or the proxy to reach it through)
+    /// can't be resolved, or the URL is HTTPS and the `tls` feature isn't enabled.
+    pub fn for_url(url: &str) -> Result<Client> {
+        Ok(split_url(url)?.0)
+    }
+
+    fn with_addrs(addrs: &[Addr]) -> Client {
+        let mut endpoint = Endpoint::new(addrs[0].clone());
+        // Other servers' 5xx statuses are for the caller to make sense of.
+        endpoint.status_retries = 0;
+        Client {
+            endpoint,
+            addrs: Arc::from(addrs),
+            chosen: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Returns the endpoint to make a request with, choosing which of the server's addresses to
+    /// use if that hasn't been settled by an earlier request.
+    fn endpoint(&self) -> Endpoint {
+        let mut endpoint = self.endpoint.clone();
+        if let Some(addr) = self.chosen.get() {
+            endpoint.addr = addr.clone();
+        } else if endpoint.choose_addr(&self.addrs) {
+            self.chosen.set(endpoint.addr.clone()).ok();
+        }
+        endpoint
+    }
+
+    /// Sets a timeout for connecting, and for each read from and write to a connection. By
+    /// default, there are none.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.endpoint.connect_timeout = Some(timeout);
+        self.endpoint.read_timeout = Some(timeout);
+        self.endpoint.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Starts a `GET` request to `path`, which can include a query string.
+    #[must_use]
+    pub fn get<'a>(&self, path: &'a str) -> Request<'a> {
+        self.request("GET", path)
+    }
+
+    /// Starts a `POST` request to `path`.
+    #[must_use]
+    pub fn post<'a>(&self, path: &'a str) -> Request<'a> {
+        self.request("POST", path)
+    }
+
+    /// Starts a `PUT` request to `path`.
+    #[must_use]
+    pub fn put<'a>(&self, path: &'a str) -> Request<'a> {
+        self.request("PUT", path)
+    }
+
+    /// Starts a request to `path` with any method.
+    #[must_use]
+    pub fn request<'a>(&self, method: &'a str, path: &'a str) -> Request<'a> {
+        Request {
+            client: self.clone(),
+            method,
+            path: Cow::Borrowed(path),
+            headers: Headers::new(),
+        }
+    }
+}
+
+/// Starts a `GET` request to `url`, with a new [`Client`] for its server.
+///
+/// # Errors
+///
+/// Returns an error like [`Client::for_url`].
+pub fn get(url: &str) -> Result<Request<'_>> {
+    request("GET", url)
+}
+
+/// Starts a `POST` request to `url`, with a new [`Client`] for its server.
+///
+/// # Errors
+///
+/// Returns an error like [`Client::for_url`].
+pub fn post(url: &str) -> Result<Request<'_>> {
+    request("POST", url)
+}
+
+/// Starts a `PUT` request to `url`, with a new [`Client`] for its server.
+///
+/// # Errors
+///
+/// Returns an error like [`Client::for_url`].
+pub fn put(url: &str) -> Result<Request<'_>> {
+    request("PUT", url)
+}
+
+/// Starts a request to `url` with any method, with a new [`Client`] for its server.
+///
+/// # Errors
+///
+/// Returns an error like [`Client::for_url`].
+pub fn request<'a>(method: &'a str, url: &'a str) -> Result<Request<'a>> {
+    let (client, path) = split_url(url)?;
+    Ok(Request {
+        client,
+        method,
+        path,
+        headers: Headers::new(),
+    })
+}
+
+/// Splits an HTTP URL into a client for its server and the path (and query) to request.
+fn split_url(url: &str) -> Result<(Client, Cow<'_, str>)> {
+    let invalid = || Error::Config(format!("could not parse {url:?} as an HTTP URL"));
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let tls = if scheme.eq_ignore_ascii_case("https") {
+        true
+    } else if scheme.eq_ignore_ascii_case("http") {
+        false
+    } else {
+        return Err(invalid());
+    };
+    if tls && !cfg!(feature = "tls") {
+        return Err(Error::Config(format!(
+            "HTTPS URLs like {url:?} need minlambda's `tls` feature"
+        )));
+    }
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    // There's a port unless the authority has no colon, or ends with a bracketed IPv6 address.
+    let (name, port) = match authority.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => (name, port.parse().map_err(|_| invalid())?),
+        _ => (authority, if tls { 443 } else { 80 }),
+    };
+    // An IPv6 address is resolved without its brackets, which the `Host` header puts back.
+    let name = (name.strip_prefix('['))
+        .and_then(|name| name.strip_suffix(']'))
+        .unwrap_or(name);
+    if name.is_empty() || name.contains(['@', '[', ']']) {
+        return Err(invalid());
+    }
+    let path = if path.starts_with('/') {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(format!("/{path}"))
+    };
+    Ok((Client::with_addrs(&Addr::resolve(name, port, tls)?), path))
+}
+
+/// A request being made with a [`Client`], to add headers to before sending it.
+#[derive(Debug)]
+pub struct Request<'a> {
+    client: Client,
+    method: &'a str,
+    path: Cow<'a, str>,
+    headers: Headers<'a>,
+}
+
+impl<'a> Request<'a> {
+    /// Adds a header. The `Host` and `User-Agent` headers are always sent, and the
+    /// `Content-Length` or `Transfer-Encoding` header is added when the request is sent.
+    #[must_use]
+    pub fn header<V: Into<Cow<'a, str>>>(mut self, name: &'a str, value: V) -> Request<'a> {
+        self.headers = self.headers.with(name, value);
+        self
+    }
+
+    /// Sends the request with `body` (which can be empty), waiting for the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the response is malformed, but not for error
+    /// statuses; check [`Response::status`] for those.
+    pub fn send(self, body: &[u8]) -> Result<Response> {
+        let Request {
+            client,
+            method,
+            path,
+            headers,
+        } = self;
+        let headers = headers.with("content-length", body.len().to_string());
+        let endpoint = &client.endpoint();
+        endpoint
+            .reconnecting(|| request_once(endpoint, method, &path, &headers, body))
+            .map_err(client_context(endpoint, method, &path))
+    }
+
+    /// Sends the request with `body` serialized as JSON, and a `Content-Type` of
+    /// `application/json`, waiting for the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization or the request fails, like for [`Request::send`].
+    pub fn send_json<S: Serialize>(self, body: &S) -> Result<Response> {
+        let body = serde_json::to_vec(body).map_err(Error::serialize)?;
+        self.header("content-type", "application/json").send(&body)
+    }
+
+    /// Starts sending the request, returning a writer for streaming the body with chunked
+    /// transfer encoding.
+    ///
+    /// Call [`RequestWriter::finish`] once the body is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if connecting or sending the request's head fails.
+    pub fn stream(self) -> Result<RequestWriter> {
+        let endpoint = self.client.endpoint();
+        let headers = self.headers.with("transfer-encoding", "chunked");
+        let stream = http_start(&endpoint, self.method, &self.path, &headers)
+            .map_err(client_context(&endpoint, self.method, &self.path))?;
+        Ok(RequestWriter {
+            stream: ChunkedWriter::new(&endpoint, stream),
+            endpoint,
+            method: String::from(self.method),
+            path: self.path.into_owned(),
+        })
+    }
+}
+
+/// A writer for streaming a request body, from [`Request::stream`].
+///
+/// A `RequestWriter` that is dropped without being finished abandons the request.
+#[derive(Debug)]
+pub struct RequestWriter {
+    endpoint: Endpoint,
+    /// The request's method and path, for error messages.
+    method: String,
+    path: String,
+    stream: ChunkedWriter,
+}
+
+impl RequestWriter {
+    /// Finishes the body, waiting for the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the response is malformed, like for
+    /// [`Request::send`].
+    pub fn finish(self) -> Result<Response> {
+        let RequestWriter {
+            endpoint,
+            method,
+            path,
+            stream,
+        } = self;
+        (stream.finish().map_err(Error::transport).and_then(send))
+            .and_then(|stream| read_response(&endpoint, stream, false))
+            .map_err(client_context(&endpoint, &method, &path))
+    }
+}
+
+impl Write for RequestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backoff, get_invocation, keep_idle, post_raw_response, read_invocation, read_response,
+        split_url, Client, Conn, Endpoint, Reuse, IDLE, MAX_RETRY_DELAY, RETRY_DELAY,
+    };
+    use crate::error::{Error, Result};
+    use crate::transport::{Addr, Connection};
+    use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+
+    /// A connection that reads a canned response and discards what's written to it.
+    #[derive(Debug)]
+    struct Canned(Cursor<Vec<u8>>);
+
+    impl Read for Canned {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Canned {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Canned {}
+
+    fn endpoint() -> Endpoint {
+        let mut endpoint = Endpoint::new(Addr::Tcp(SocketAddr::from(([127, 0, 0, 1], 9))));
+        endpoint.keep_alive = false;
+        endpoint
+    }
+
+    fn response_from(endpoint: &Endpoint, response: &[u8]) -> Result<super::Response> {
+        let stream = Conn::new(endpoint, Box::new(Canned(Cursor::new(response.to_vec()))));
+        read_response(endpoint, stream, false)
+    }
+
+    fn response(response: &[u8]) -> Result<super::Response> {
+        response_from(&endpoint(), response)
+    }
+
+    fn body(response: &[u8]) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self::response(response)
+            .map_err(io::Error::from)?
+            .into_body()
+            .read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    fn is_protocol(result: Result<super::Response>, message: &str) -> bool {
+        matches!(result, Err(Error::Protocol(m)) if m == message)
+    }
+
+    #[test]
+    fn fixed_length() {
+        assert_eq!(
+            body(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, next response").unwrap(),
+            b"hello"
+        );
+        let err = body(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn until_close() {
+        let response = response(b"HTTP/1.1 200 OK\r\n\r\nhello").unwrap();
+        assert_eq!(response.body.reuse, Reuse::Close);
+        assert_eq!(
+            body(b"HTTP/1.1 200 OK\r\n\r\nhello\r\n0\r\n\r\n").unwrap(),
+            b"hello\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn invalid_content_length() {
+        for len in ["lots", "", "+5", "-1", "5, 5", "99999999999999999999999"] {
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {len}\r\n\r\nhello");
+            assert!(is_protocol(
+                response(head.as_bytes()),
+                "invalid Content-Length"
+            ));
+        }
+        assert_eq!(
+            body(b"HTTP/1.1 200 OK\r\nContent-Length:  5 \r\n\r\nhello").unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn headers() {
+        let response = response(
+            b"HTTP/1.1 200 OK\r\nX-One:  1 \r\nnot a header\r\nx-one: 2\r\nX-Empty:\r\n\
+              Content-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.header("X-ONE"), Some("1"));
+        assert_eq!(response.header("x-empty"), Some(""));
+        assert_eq!(response.headers().count(), 4);
+        assert_eq!(response.body.reuse, Reuse::Idle);
+    }
+
+    #[test]
+    fn idle_connections() {
+        let addr = |port| Addr::Tcp(SocketAddr::from(([127, 0, 0, 1], port)));
+        let idle = || {
+            IDLE.with(|idle| {
+                idle.borrow()
+                    .iter()
+                    .map(|(addr, _)| addr.clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        let canned = || Conn::new(&endpoint(), Box::new(Canned(Cursor::new(Vec::new()))));
+        for port in 1..=5 {
+            keep_idle(addr(port), canned());
+        }
+        keep_idle(addr(3), canned());
+        assert_eq!(idle(), [addr(2), addr(4), addr(5), addr(3)]);
+        // Nothing listens on these ports, so connecting succeeds only by reusing a connection.
+        assert!(Endpoint::new(addr(4)).connect().is_ok());
+        assert_eq!(idle(), [addr(2), addr(5), addr(3)]);
+        assert!(Endpoint::new(addr(4)).connect().is_err());
+    }
+
+    #[test]
+    fn connection_close() {
+        for head in [
+            &b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nConnection: Close\r\nContent-Length: 0\r\n\r\n",
+            // Closed before the end of the headers.
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n",
+        ] {
+            assert_eq!(response(head).unwrap().body.reuse, Reuse::Close);
+        }
+    }
+
+    #[test]
+    fn interim_responses() {
+        let response = response(
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a>\r\n\r\n\
+              HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status(), 202);
+        assert_eq!(response.header("Link"), None);
+        assert_eq!(
+            self::response(b"HTTP/1.1 101 Switching Protocols\r\n\r\n")
+                .unwrap()
+                .status(),
+            101
+        );
+    }
+
+    #[test]
+    fn malformed_status_line() {
+        assert!(matches!(
+            response(b""),
+            Err(Error::Transport(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        for head in [
+            &b"HTTP/1.1\r\n\r\n"[..],
+            b"HTTP/1.1 2\r\n\r\n",
+            b"HTTP/1.1 2x0 OK\r\n\r\n",
+            b"HTTP/2 200 OK\r\n\r\n",
+            b"http/1.1 200 OK\r\n\r\n",
+            b"SSH-2.0-OpenSSH_9.0\r\n",
+        ] {
+            assert!(is_protocol(response(head), "malformed HTTP response"));
+        }
+    }
+
+    #[test]
+    fn chunked() {
+        assert_eq!(
+            body(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\nA\r\n, chunked!\r\n0\r\n\r\n"
+            )
+            .unwrap(),
+            b"hello, chunked!"
+        );
+        // Chunked is the last coding applied, whatever the case.
+        assert_eq!(
+            body(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n"
+            )
+            .unwrap(),
+            b"hi"
+        );
+        // If it isn't, the body lasts until the connection closes.
+        assert_eq!(
+            body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked, gzip\r\n\r\n2\r\nhi").unwrap(),
+            b"2\r\nhi"
+        );
+    }
+
+    #[test]
+    fn chunk_extensions_and_trailers() {
+        assert_eq!(
+            body(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5;name=value\r\nhello\r\n1 ; a ; b=\"c\"\r\n!\r\n0;last\r\n\
+                  X-Checksum: abc\r\nX-Other: def\r\n\r\n"
+            )
+            .unwrap(),
+            b"hello!"
+        );
+    }
+
+    #[test]
+    fn malformed_chunks() {
+        for body in [
+            &b"x\r\nhello\r\n0\r\n\r\n"[..],
+            b"\r\nhello\r\n0\r\n\r\n",
+            b";ext\r\nhello\r\n0\r\n\r\n",
+            b"+5\r\nhello\r\n0\r\n\r\n",
+            b"fffffffffffffffffffff\r\nhello\r\n0\r\n\r\n",
+        ] {
+            let response = [
+                &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+                body,
+            ]
+            .concat();
+            let err = self::body(&response).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "malformed HTTP response: invalid chunk length"
+            );
+        }
+        let err =
+            body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello!!0\r\n\r\n")
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed HTTP response: missing CRLF after chunk"
+        );
+        let err =
+            body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversized_head() {
+        let mut endpoint = endpoint();
+        endpoint.max_header_line = 32;
+        endpoint.max_headers = 64;
+        let long_status = format!("HTTP/1.1 200 {}\r\n\r\n", "O".repeat(32));
+        assert!(is_protocol(
+            response_from(&endpoint, long_status.as_bytes()),
+            "response line too long"
+        ));
+        let long_header = format!("HTTP/1.1 200 OK\r\nX: {}\r\n\r\n", "a".repeat(32));
+        assert!(is_protocol(
+            response_from(&endpoint, long_header.as_bytes()),
+            "response line too long"
+        ));
+        let many_headers = format!("HTTP/1.1 200 OK\r\n{}\r\n", "X-Header: value\r\n".repeat(5));
+        assert!(is_protocol(
+            response_from(&endpoint, many_headers.as_bytes()),
+            "response headers too long"
+        ));
+        let few_headers = format!("HTTP/1.1 200 OK\r\n{}\r\n", "X-Header: value\r\n".repeat(3));
+        assert!(response_from(&endpoint, few_headers.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn body_limit() {
+        let mut body = response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+            .unwrap()
+            .into_body();
+        body.limit = 4;
+        let err = body.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            err.downcast::<Error>(),
+            Ok(Error::PayloadTooLarge { limit: 4 })
+        ));
+    }
+
+    #[test]
+    fn retries() {
+        const REFUSED: &[u8] = b"";
+        const BUSY: &[u8] = b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\n\r\n";
+        const MISSING: &[u8] = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        const ACCEPTED: &[u8] = b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n";
+
+        let mut endpoint = endpoint();
+        endpoint.retries = 1;
+        endpoint.status_retries = 2;
+        let attempts = |responses: &[&[u8]]| {
+            let mut responses = responses.iter();
+            let mut attempts = 0;
+            let result = endpoint.retrying(|| {
+                attempts += 1;
+                match responses.next() {
+                    Some(response) if !response.is_empty() => response_from(&endpoint, response),
+                    _ => Err(Error::Transport(io::ErrorKind::ConnectionRefused.into())),
+                }
+            });
+            (result.map(|_| ()), attempts)
+        };
+
+        // Connection failures and error statuses are counted separately.
+        let (result, count) = attempts(&[REFUSED, BUSY, BUSY, ACCEPTED]);
+        assert!(result.is_ok());
+        assert_eq!(count, 4);
+        let (result, count) = attempts(&[BUSY, BUSY, BUSY, ACCEPTED]);
+        assert!(matches!(result, Err(Error::RuntimeApi { status: 429, .. })));
+        assert_eq!(count, 3);
+        let (result, count) = attempts(&[REFUSED, REFUSED, ACCEPTED]);
+        assert!(matches!(result, Err(Error::Transport(_))));
+        assert_eq!(count, 2);
+        let (result, count) = attempts(&[MISSING, ACCEPTED]);
+        assert!(matches!(result, Err(Error::RuntimeApi { status: 404, .. })));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn backoff_delays() {
+        for retry in 0..20 {
+            let delay = backoff(retry);
+            assert!(delay >= (MAX_RETRY_DELAY / 2).min(RETRY_DELAY * (1 << retry) / 2));
+            assert!(delay <= MAX_RETRY_DELAY);
+        }
+        assert!(backoff(0) <= RETRY_DELAY);
+    }
+
+    #[test]
+    fn invocation_headers() {
+        let invocation = |head: &str| {
+            let endpoint = endpoint();
+            let stream = Conn::new(&endpoint, Box::new(Canned(Cursor::new(head.into()))));
+            read_invocation(&endpoint, stream)
+        };
+        let (context, _) = invocation(
+            "HTTP/1.1 200 OK\r\nLambda-Runtime-Aws-Request-Id: r1\r\n\
+             Lambda-Runtime-Deadline-Ms: 1700000000000\r\n\
+             Lambda-Runtime-Invoked-Function-Arn: arn:aws:lambda:us-east-1:1:function:f\r\n\
+             Lambda-Runtime-Trace-Id: Root=1-2-3\r\nContent-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(context.request_id, "r1");
+        assert_eq!(context.deadline.as_unix_millis(), 1_700_000_000_000);
+        assert_eq!(
+            context.invoked_function_arn,
+            "arn:aws:lambda:us-east-1:1:function:f"
+        );
+        assert_eq!(context.trace_id.as_deref(), Some("Root=1-2-3"));
+        // Only the request ID is required.
+        let (context, _) = invocation(
+            "HTTP/1.1 200 OK\r\nLambda-Runtime-Aws-Request-Id: r2\r\n\
+             Lambda-Runtime-Deadline-Ms: soon\r\nContent-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(context.request_id, "r2");
+        assert!(context.deadline.remaining().as_secs() > 14 * 60);
+        assert_eq!(context.invoked_function_arn, "");
+        assert!(matches!(
+            invocation("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+            Err(Error::Protocol(message)) if message == "missing request ID"
+        ));
+    }
+
+    /// Reads a request's head and body from `stream`, returning its request line.
+    fn read_request(stream: &mut impl BufRead) -> String {
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).unwrap();
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                len = value.trim().parse().unwrap();
+            }
+        }
+        stream.read_exact(&mut vec![0; len]).unwrap();
+        request_line.trim_end().to_owned()
+    }
+
+    #[test]
+    fn pipelining() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut endpoint = Endpoint::new(Addr::Tcp(listener.local_addr().unwrap()));
+        endpoint.pipeline = true;
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            // Both requests arrive on the one connection before either is answered.
+            let requests = [read_request(&mut reader), read_request(&mut reader)];
+            (&stream)
+                .write_all(
+                    b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n\
+                      HTTP/1.1 200 OK\r\nLambda-Runtime-Aws-Request-Id: r2\r\n\
+                      Content-Length: 2\r\n\r\n{}",
+                )
+                .unwrap();
+            requests
+        });
+
+        post_raw_response(
+            &endpoint,
+            "invocation/r1/response",
+            "application/json",
+            b"{}",
+        )
+        .unwrap();
+        let (context, mut body) = get_invocation(&endpoint, "invocation/next").unwrap();
+        let mut event = Vec::new();
+        body.read_to_end(&mut event).unwrap();
+        assert_eq!(
+            (context.request_id.as_str(), event.as_slice()),
+            ("r2", &b"{}"[..])
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests,
+            [
+                "POST /2018-06-01/runtime/invocation/r1/response HTTP/1.1",
+                "GET /2018-06-01/runtime/invocation/next HTTP/1.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn chooses_addr_lazily() {
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addrs = [closed, listener.local_addr().unwrap()].map(Addr::Tcp);
+        let client = Client::with_addrs(&addrs);
+        let err = listener.accept().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(client.clone().endpoint().addr, addrs[1]);
+        assert!(listener.accept().is_ok());
+        assert_eq!(client.chosen.get(), Some(&addrs[1]));
+    }
+
+    #[test]
+    fn split_url_ipv6() {
+        let (client, path) = split_url("http://[::1]:8080/items?id=1").unwrap();
+        assert_eq!(client.endpoint.addr.to_string(), "[::1]:8080");
+        assert_eq!(path, "/items?id=1");
+        let (client, path) = split_url("http://[::1]").unwrap();
+        assert_eq!(client.endpoint.addr.to_string(), "[::1]");
+        assert_eq!(path, "/");
+    }
+
+    #[test]
+    fn split_url_ipv4() {
+        let (client, path) = split_url("HTTP://127.0.0.1:80?q#fragment").unwrap();
+        assert_eq!(client.endpoint.addr.to_string(), "127.0.0.1");
+        assert_eq!(path, "/?q");
+    }
+
+    #[test]
+    fn split_url_invalid() {
+        for url in [
+            "127.0.0.1:8080",
+            "ftp://127.0.0.1/",
+            "http:///path",
+            "http://user@127.0.0.1/",
+            "http://127.0.0.1:port/",
+            "http://[::1/",
+            "http://[[::1]]/",
+        ] {
+            assert!(split_url(url).is_err(), "{}", url);
+        }
     }
 }
@@ -0,0 +1,278 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::{Context, Handler, MaybeEmpty, RouteError};
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+
+/// The rest of the layer stack and the handler, as passed to [`Layer::call`].
+///
+/// It takes the raw event and returns the response as serialized JSON, or `None` if the response
+/// is empty (when the handler returns [`NoResponse`](crate::NoResponse)).
+pub type Next<'a> = &'a mut dyn FnMut(&[u8]) -> Result<Option<Vec<u8>>, RouteError>;
+
+/// Middleware that wraps a handler.
+///
+/// A layer sees each invocation's context and raw event, and the handler's result before it's
+/// posted to the runtime API. It can change either one, or return an error without calling the
+/// handler at all. Stack layers with [`layers!`](crate::layers) and wrap a handler with
+/// [`Layer::wrap`]:
+///
+/// ```rust,no_run
+/// use minlambda::{Context, Layer, Next, RouteError};
+/// use std::time::Instant;
+///
+/// #[derive(Debug)]
+/// struct Timing;
+///
+/// impl Layer for Timing {
+///     fn call(
+///         &mut self,
+///         context: &Context,
+///         event: &[u8],
+///         next: Next<'_>,
+///     ) -> Result<Option<Vec<u8>>, RouteError> {
+///         let start = Instant::now();
+///         let result = next(event);
+///         eprintln!("{} took {:?}", context.request_id, start.elapsed());
+///         result
+///     }
+/// }
+///
+/// #[derive(Debug)]
+/// struct Logging;
+///
+/// impl Layer for Logging {
+///     fn call(
+///         &mut self,
+///         _context: &Context,
+///         event: &[u8],
+///         next: Next<'_>,
+///     ) -> Result<Option<Vec<u8>>, RouteError> {
+///         eprintln!("event: {}", String::from_utf8_lossy(event));
+///         next(event)
+///     }
+/// }
+///
+/// fn main() -> ! {
+///     minlambda::run(minlambda::layers![Timing, Logging].wrap(|name: String| {
+///         Ok::<_, std::convert::Infallible>(format!("Hello, {name}!"))
+///     }))
+/// }
+/// ```
+pub trait Layer {
+    /// Handles an invocation by calling `next` with the (possibly modified) event.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported to the runtime API as the invocation's error.
+    fn call(
+        &mut self,
+        context: &Context,
+        event: &[u8],
+        next: Next<'_>,
+    ) -> Result<Option<Vec<u8>>, RouteError>;
+
+    /// Wraps a handler in this layer.
+    fn wrap<H, D>(self, handler: H) -> Layered<Self, H, D>
+    where
+        Self: Sized,
+        H: Handler<D>,
+        D: DeserializeOwned,
+    {
+        Layered {
+            layer: self,
+            handler,
+            _event: PhantomData,
+        }
+    }
+}
+
+impl<L: Layer + ?Sized> Layer for &mut L {
+    fn call(
+        &mut self,
+        context: &Context,
+        event: &[u8],
+        next: Next<'_>,
+    ) -> Result<Option<Vec<u8>>, RouteError> {
+        (**self).call(context, event, next)
+    }
+}
+
+/// Two layers, one wrapping the other.
+///
+/// This is usually built with [`layers!`](crate::layers).
+#[derive(Debug, Clone)]
+pub struct Stack<A, B> {
+    outer: A,
+    inner: B,
+}
+
+impl<A: Layer, B: Layer> Stack<A, B> {
+    /// Creates a stack where `outer` wraps `inner`.
+    pub fn new(outer: A, inner: B) -> Stack<A, B> {
+        Stack { outer, inner }
+    }
+}
+
+impl<A: Layer, B: Layer> Layer for Stack<A, B> {
+    fn call(
+        &mut self,
+        context: &Context,
+        event: &[u8],
+        next: Next<'_>,
+    ) -> Result<Option<Vec<u8>>, RouteError> {
+        let inner = &mut self.inner;
+        self.outer.call(context, event, &mut |event| {
+            inner.call(context, event, next)
+        })
+    }
+}
+
+/// Stacks layers, outermost first.
+///
+/// `layers![Timing, Logging]` runs `Timing` around `Logging` around the handler.
+#[macro_export]
+macro_rules! layers {
+    ($layer:expr $(,)?) => {
+        $layer
+    };
+    ($outer:expr, $($inner:expr),+ $(,)?) => {
+        $crate::Stack::new($outer, $crate::layers!($($inner),+))
+    };
+}
+
+/// A handler wrapped in a [`Layer`], created by [`Layer::wrap`].
+pub struct Layered<L, H, D> {
+    layer: L,
+    handler: H,
+    _event: PhantomData<fn(D)>,
+}
+
+impl<L, H, D> Handler<Box<RawValue>> for Layered<L, H, D>
+where
+    L: Layer,
+    H: Handler<D>,
+    D: DeserializeOwned,
+{
+    type Response = MaybeEmpty<Box<RawValue>>;
+    type Error = RouteError;
+
+    fn handle(&mut self, event: Box<RawValue>) -> Result<MaybeEmpty<Box<RawValue>>, RouteError> {
+        let context = crate::context().ok_or_else(|| {
+            RouteError::unrouted(String::from(
+                "layered handler called outside of an invocation",
+            ))
+        })?;
+        let handler = &mut self.handler;
+        let response = self
+            .layer
+            .call(&context, event.get().as_bytes(), &mut |event| {
+                let event = crate::de::from_slice(event).map_err(|err| RouteError::new(&err))?;
+                let response = handler.handle(event).map_err(|err| RouteError::new(&err))?;
+                if crate::response::is_empty(&response) {
+                    return Ok(None);
+                }
+                serde_json::to_vec(&response)
+                    .map(Some)
+                    .map_err(|err| RouteError::new(&err))
+            })?;
+        response
+            .map(|response| {
+                let response = String::from_utf8(response).map_err(|err| RouteError::new(&err))?;
+                RawValue::from_string(response).map_err(|err| RouteError::new(&err))
+            })
+            .transpose()
+            .map(MaybeEmpty)
+    }
+}
+
+impl<L: Debug, H, D> Debug for Layered<L, H, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Layered")
+            .field("layer", &self.layer)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Layer, Next};
+    use crate::context::{self, Deadline};
+    use crate::{Context, Handler, MaybeEmpty, RouteError};
+    use serde_json::value::RawValue;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records when it's called, short-circuits `"skip"` events, lowercases other events, and
+    /// wraps responses in an object keyed by its name.
+    struct Named(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Layer for Named {
+        fn call(
+            &mut self,
+            _context: &Context,
+            event: &[u8],
+            next: Next<'_>,
+        ) -> Result<Option<Vec<u8>>, RouteError> {
+            self.1.borrow_mut().push(self.0);
+            if event == b"\"skip\"" {
+                return Ok(Some(b"\"skipped\"".to_vec()));
+            }
+            let event = String::from_utf8_lossy(event).to_lowercase();
+            Ok(next(event.as_bytes())?.map(|response| {
+                let response = String::from_utf8(response).unwrap();
+                format!(r#"{{"{}":{}}}"#, self.0, response).into_bytes()
+            }))
+        }
+    }
+
+    /// Handles `event`, returning the response as JSON, or `None` if it's empty.
+    fn handle<H>(handler: &mut H, event: &str) -> Option<String>
+    where
+        H: Handler<Box<RawValue>, Response = MaybeEmpty<Box<RawValue>>>,
+    {
+        let event = RawValue::from_string(event.to_owned()).unwrap();
+        let MaybeEmpty(response) = handler.handle(event).map_err(|_| ()).unwrap();
+        response.map(|response| response.get().to_owned())
+    }
+
+    #[test]
+    fn layers() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let layers = crate::layers![
+            Named("outer", Rc::clone(&calls)),
+            Named("inner", Rc::clone(&calls)),
+        ];
+        let mut handler = layers.wrap(|event: Option<String>| Ok::<_, String>(MaybeEmpty(event)));
+        assert!(handler
+            .handle(RawValue::from_string(String::from("1")).unwrap())
+            .is_err());
+
+        context::set_current(Some(Context {
+            request_id: String::from("r1"),
+            deadline: Deadline::longest(),
+            invoked_function_arn: String::new(),
+            trace_id: None,
+            client_context: None,
+            identity: None,
+        }));
+        assert_eq!(
+            handle(&mut handler, r#""EVENT""#).as_deref(),
+            Some(r#"{"outer":{"inner":"event"}}"#)
+        );
+        assert_eq!(*calls.borrow(), ["outer", "inner"]);
+        assert_eq!(handle(&mut handler, "null"), None);
+        assert_eq!(
+            handle(&mut handler, r#""skip""#).as_deref(),
+            Some(r#""skipped""#)
+        );
+        assert_eq!(
+            handle(&mut handler, r#""Skip""#).as_deref(),
+            Some(r#"{"outer":"skipped"}"#)
+        );
+        context::set_current(None);
+    }
+}
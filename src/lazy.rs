@@ -0,0 +1,70 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::ErrorTypeName;
+use std::fmt::{self, Debug};
+use std::sync::OnceLock;
+
+/// A value constructed the first time it's needed, created by [`lazy_init`].
+pub struct LazyInit<T: 'static, F> {
+    value: OnceLock<&'static T>,
+    init: F,
+}
+
+/// Creates a value that is constructed by `init` on first use, such as an SDK client that is
+/// expensive to set up.
+///
+/// [`LazyInit::get`] returns a `&'static T`, so the value can be used from anywhere (including
+/// other threads) without cloning it into each invocation. If construction fails, the error is
+/// reported as an initialization error to the runtime API of the last [`Runtime`](crate::Runtime)
+/// built (or the one in `AWS_LAMBDA_RUNTIME_API`, if the value is used before any is) and the
+/// process exits.
+///
+/// ```rust,no_run
+/// fn main() -> ! {
+///     let config = minlambda::lazy_init(|| std::fs::read_to_string("/var/task/config.json"));
+///
+///     minlambda::run(move |key: String| {
+///         let config: &'static String = config.get();
+///         Ok::<_, std::convert::Infallible>(config.contains(&key))
+///     })
+/// }
+/// ```
+pub fn lazy_init<T, E, F>(init: F) -> LazyInit<T, F>
+where
+    F: Fn() -> Result<T, E>,
+    E: ErrorTypeName + 'static,
+{
+    LazyInit {
+        value: OnceLock::new(),
+        init,
+    }
+}
+
+impl<T, E, F> LazyInit<T, F>
+where
+    F: Fn() -> Result<T, E>,
+    E: ErrorTypeName + 'static,
+{
+    /// Returns the value, constructing it if this is the first call.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if construction fails and the error cannot be reported to the runtime
+    /// API.
+    pub fn get(&self) -> &'static T {
+        self.value.get_or_init(|| match (self.init)() {
+            // The value lives as long as the process does, as it would in a `static`.
+            Ok(value) => Box::leak(Box::new(value)),
+            Err(err) => crate::client::lazy_init_failed(&err),
+        })
+    }
+}
+
+impl<T: Debug, F> Debug for LazyInit<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyInit")
+            .field("value", &self.value.get())
+            .finish_non_exhaustive()
+    }
+}
@@ -17,21 +17,33 @@
 //! minlambda implements the [AWS Lambda runtime interface][interface], deserializing events and
 //! serializing responses with [Serde JSON][json].
 //!
-//! To communicate with the runtime API over HTTP, minlambda uses a purpose-built HTTP client.
+//! To communicate with the runtime API over HTTP, minlambda uses a purpose-built HTTP client. It's
+//! also available as [`minlambda::http`](http), for simple requests to other servers. Setting the
+//! `MINLAMBDA_DEBUG_HTTP` environment variable to `1` logs the request lines, status lines, and
+//! headers it sends and receives (but not bodies) to stderr.
+//!
+//! minlambda can also power [Lambda extensions][extensions-api], with the client for the
+//! Extensions API in [`minlambda::extensions`](extensions), and subscriptions to the Telemetry API
+//! in [`minlambda::telemetry`](telemetry).
 //!
 //! [interface]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+//! [extensions-api]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-extensions-api.html
 //! [json]: https://docs.rs/serde_json
 //!
 //! # What it doesn't
 //!
-//! minlambda doesn't parse [response headers in the invocation event][next] (other than the
-//! request ID). This includes the function deadline, function ARN, AWS X-Ray tracing header, or
-//! additional AWS Mobile SDK data. The crate author has never needed these and, well, this is a
-//! minimal runtime.
+//! minlambda parses the [response headers in the invocation event][next] into a
+//! `minlambda::Context`: the request ID, function deadline, function ARN, AWS X-Ray tracing header,
+//! and AWS Mobile SDK client context and Cognito identity data. These are available to handlers
+//! passed to `minlambda::run_with_context`, for passing the tracing header along to AWS SDKs.
+//! Beyond that, this is a minimal runtime.
 //!
-//! minlambda doesn't run your handler in an async runtime. If you're using async code, you can
-//! create a runtime outside of `lambda::run` and call its blocking function (e.g. Tokio's
-//! `Runtime::block_on`). [An example for Tokio is available.][tokio-example]
+//! minlambda doesn't run your handler in an async runtime by default. If you're using async code
+//! with Tokio, enable the `tokio` feature and use `minlambda::run_async`, which drives your handler
+//! on a current-thread runtime. [An example for Tokio is available.][tokio-example] For futures
+//! that don't need a runtime's I/O or timers, `minlambda::run_future` polls them with a tiny
+//! built-in executor. Other runtimes can be plugged in with `minlambda::run_async_on`; adapters for
+//! smol and async-std are available with the `smol` and `async-std` features.
 //!
 //! [next]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html#runtimes-api-next
 //! [tokio-example]: https://github.com/iliana/minlambda/blob/matriarch/examples/async.rs
@@ -96,54 +108,233 @@
 )]
 #![warn(clippy::pedantic)]
 
-mod http;
+mod background;
+mod base64;
+mod cancel;
+mod client;
+mod context;
+mod de;
+mod error;
+pub mod executor;
+pub mod extensions;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod handler;
+pub mod http;
+mod layer;
+mod lazy;
+mod listener;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod panic;
+mod proxy;
+mod registry;
+#[cfg(feature = "reporting")]
+pub mod reporting;
+mod response;
+mod router;
+mod runtime;
+#[cfg(feature = "signal")]
+mod service;
+#[cfg(feature = "signal")]
+mod shutdown;
+pub mod snapstart;
+pub mod telemetry;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tower")]
+mod tower;
+pub mod transport;
+mod watchdog;
+
+pub use crate::background::{defer, spawn_background};
+pub use crate::cancel::{run_cancellable, CancellationToken};
+pub use crate::client::Client;
+pub use crate::context::{
+    context, ClientApplication, ClientContext, CognitoIdentity, Context, Deadline,
+};
+pub use crate::error::{Error, ErrorReport, ErrorTypeName, Fatal, Traced};
+pub use crate::executor::Executor;
+pub use crate::handler::{BorrowedHandler, Handler};
+pub use crate::http::{Body, ResponseWriter};
+pub use crate::layer::{Layer, Layered, Next, Stack};
+pub use crate::lazy::{lazy_init, LazyInit};
+pub use crate::registry::{run_by_function_name, run_registry, Registry};
+pub use crate::response::{MaybeEmpty, NoResponse, RawJson};
+pub use crate::router::{is_serverless_warmup, EventSource, RouteError, Router};
+pub use crate::runtime::{Builder, DeserializeFailure, Runtime};
+#[cfg(feature = "signal")]
+pub use crate::service::{run_service, ServiceHandler};
+#[cfg(feature = "signal")]
+pub use crate::shutdown::on_shutdown;
+#[cfg(feature = "tower")]
+pub use crate::tower::{run_tower, run_tower_on};
+#[cfg(feature = "macros")]
+pub use minlambda_macros::{handler, main};
 
 use serde::{de::DeserializeOwned, Serialize};
-use std::net::SocketAddr;
 
 /// Retrieves invocation events, calls your handler, and sends back response data within the Lambda
 /// execution environment.
 ///
+/// The handler is usually a closure of the form `FnMut(D) -> Result<S, E>`, but can be anything
+/// that implements [`Handler`]. The error type can be anything that implements [`ErrorTypeName`],
+/// which reports it with its Rust type name as the `errorType` unless the implementation picks
+/// another. For quick handlers, `Box<dyn std::error::Error>` (with or without `Send + Sync`) lets
+/// `?` work on any error, and reports the error's source chain:
+///
+/// ```rust,no_run
+/// use std::error::Error;
+///
+/// fn main() -> ! {
+///     minlambda::run(|path: String| -> Result<u64, Box<dyn Error + Send + Sync>> {
+///         let contents = std::fs::read_to_string(path)?;
+///         Ok(contents.trim().parse()?)
+///     })
+/// }
+/// ```
+///
 /// This function [does not return][diverging] (Lambda will kill processes when unused).
 ///
 /// # Panics
 ///
 /// This function panics on two fatal error conditions:
 ///
-/// * Failing to parse the `AWS_LAMBDA_RUNTIME_API` environment variable as a [`SocketAddr`].
+/// * Failing to resolve the `AWS_LAMBDA_RUNTIME_API` environment variable to a
+///   [`SocketAddr`](std::net::SocketAddr).
 /// * Failing to report an error to the runtime interface.
 ///
 /// [diverging]: https://doc.rust-lang.org/stable/rust-by-example/fn/diverging.html
-pub fn run<F, D, S, E>(handler: F) -> !
+pub fn run<H, D>(handler: H) -> !
 where
-    F: FnMut(D) -> Result<S, E>,
+    H: Handler<D>,
+    D: DeserializeOwned,
+{
+    default_runtime().run(handler)
+}
+
+/// [`run`], with an initialization step.
+///
+/// `init` is called once, before the first invocation is requested, to set up state shared by all
+/// invocations (such as SDK clients or configuration). If it fails, the error is reported to the
+/// runtime API as an initialization error and the process exits. Otherwise, the handler is called
+/// for each invocation with a mutable reference to the state.
+///
+/// ```rust,no_run
+/// fn main() -> ! {
+///     minlambda::run_with_init(
+///         || std::env::var("GREETING"),
+///         |greeting: &mut String, name: String| {
+///             Ok::<_, std::convert::Infallible>(format!("{greeting}, {name}!"))
+///         },
+///     )
+/// }
+/// ```
+///
+/// This function is otherwise the same as `run`: it does not return.
+///
+/// # Panics
+///
+/// In addition to the panics described in `run`, this function panics if it cannot report an
+/// initialization error.
+pub fn run_with_init<I, T, IE, F, D, S, E>(init: I, handler: F) -> !
+where
+    I: FnOnce() -> Result<T, IE>,
+    IE: ErrorTypeName + 'static,
+    F: FnMut(&mut T, D) -> Result<S, E>,
     D: DeserializeOwned,
     S: Serialize,
-    E: std::fmt::Display + 'static,
+    E: ErrorTypeName + 'static,
 {
-    let addr: SocketAddr = std::env::var("AWS_LAMBDA_RUNTIME_API")
-        .expect("could not get $AWS_LAMBDA_RUNTIME_API")
-        .parse()
-        .expect("could not parse $AWS_LAMBDA_RUNTIME_API as SocketAddr");
+    let runtime = default_runtime();
+    let mut state = init()
+        .unwrap_or_else(|err| runtime.init_failed(err.error_type_name(), &error::message(&err)));
     let mut handler = handler;
+    runtime.run(|event: D| handler(&mut state, event))
+}
 
+/// [`run`], for handlers that want information about the invocation.
+///
+/// The handler receives a [`Context`] for each invocation alongside the event.
+///
+/// This function is otherwise the same as `run`: it does not return.
+///
+/// # Panics
+///
+/// This function panics on the same fatal error conditions as `run`.
+pub fn run_with_context<F, D, S, E>(handler: F) -> !
+where
+    F: FnMut(Context, D) -> Result<S, E>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    default_runtime().run_with_context(handler)
+}
+
+/// [`run`], until a condition is met.
+///
+/// After each invocation, `until` is called with the number of invocations handled so far; when
+/// it returns `true`, this function returns. For example, `|count| count == 100` exits after 100
+/// invocations. Invocations count whether the handler succeeded or failed, but failing to receive
+/// one (such as when the runtime API can't be reached) doesn't count, and `until` isn't called
+/// then. This is useful for controlled process recycling and for soak-testing the runtime outside
+/// Lambda.
+///
+/// This function is otherwise the same as `run`.
+///
+/// # Panics
+///
+/// This function panics on the same fatal error conditions as `run`.
+pub fn run_until<H, P, D>(handler: H, until: P)
+where
+    H: Handler<D>,
+    P: FnMut(u64) -> bool,
+    D: DeserializeOwned,
+{
+    let runtime = default_runtime();
+    let mut handler = handler;
+    let mut until = until;
+    let mut count = 0;
     loop {
-        if let Err(inner_err) = run_inner(addr, &mut handler) {
-            if let Err(init_err) = http::post_error(
-                addr,
-                "init/error",
-                "minlambda::Error",
-                &inner_err.to_string(),
-            ) {
-                panic!(
-                    "failed to report initialization error: {:?}\ncaused by: {:?}",
-                    init_err, inner_err
-                );
-            }
+        let received = runtime.step(&mut |runtime: &Runtime, context, body| {
+            runtime.handle(context, body, |_, event| handler.handle(event))
+        });
+        if !received {
+            continue;
+        }
+        count += 1;
+        if until(count) {
+            return;
         }
     }
 }
 
+/// [`run`], for exactly one invocation.
+///
+/// This function retrieves a single invocation event, calls your handler, sends back the response
+/// (or the handler's error), and returns. This is useful for driving the runtime from a test
+/// harness or supervisor process.
+///
+/// # Errors
+///
+/// Unlike `run`, errors receiving the invocation (such as failing to connect to the runtime API)
+/// are returned to the caller rather than reported to the runtime API, as are errors reporting the
+/// invocation's result.
+pub fn run_once<F, D, S, E>(handler: F) -> Result<(), Error>
+where
+    F: FnOnce(D) -> Result<S, E>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    Runtime::builder()
+        .build()?
+        .invoke_next(|runtime, context, body| {
+            runtime.handle(context, body, |_, event| handler(event))
+        })
+}
+
 /// [`run`], for handlers that don't return [`Result`].
 ///
 /// This function is otherwise the same as `run`: it does not return and will panic on certain
@@ -155,27 +346,290 @@ where
     S: Serialize,
 {
     let mut handler = handler;
-    run(|event| Result::Ok::<_, std::convert::Infallible>(handler(event)))
+    run(|event: D| Result::Ok::<_, std::convert::Infallible>(handler(event)))
 }
 
-fn run_inner<F, D, S, E>(addr: SocketAddr, handler: &mut F) -> std::io::Result<()>
+/// [`run`], reporting errors by their [`Display`](std::fmt::Display) output alone, for error types
+/// from other crates and the like.
+///
+/// The Rust type name of the error is reported as its `errorType` and its `Display` output as its
+/// `errorMessage`, without looking for an [`ErrorTypeName`] implementation or a source chain, even
+/// for error types `run` knows about.
+///
+/// This function is otherwise the same as `run`: it does not return.
+pub fn run_display<F, D, S, E>(handler: F) -> !
 where
     F: FnMut(D) -> Result<S, E>,
     D: DeserializeOwned,
     S: Serialize,
     E: std::fmt::Display + 'static,
 {
-    http::get(addr, "invocation/next").and_then(|(request_id, body)| match handler(body) {
-        Ok(response) => http::post(
-            addr,
-            &format!("invocation/{}/response", request_id),
-            &response,
-        ),
-        Err(err) => http::post_error(
-            addr,
-            &format!("invocation/{}/error", request_id),
-            std::any::type_name::<E>(),
-            &err.to_string(),
-        ),
+    let mut handler = handler;
+    run(move |event: D| handler(event).map_err(error::Displayed))
+}
+
+/// [`run`], for handlers that work with raw bytes instead of Serde types.
+///
+/// The handler receives the invocation event body as-is, and returns the response body along with
+/// its content type (e.g. `"application/octet-stream"`). No JSON parsing or serialization takes
+/// place. The current [`Context`] is available from [`context()`].
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_raw<F, C, E>(handler: F) -> !
+where
+    F: FnMut(&[u8]) -> Result<(Vec<u8>, C), E>,
+    C: AsRef<str>,
+    E: ErrorTypeName + 'static,
+{
+    let mut handler = handler;
+    default_runtime().run_loop(|runtime, context, mut body| {
+        let event = runtime.read_body(&mut body)?;
+        match handler(&event) {
+            _ if !crate::watchdog::claim() => Ok(()),
+            Ok((response, content_type)) => (runtime.client()).respond_raw(
+                &context.request_id,
+                content_type.as_ref(),
+                &response,
+            ),
+            Err(err) => runtime.report_error(&context.request_id, &err),
+        }
+    })
+}
+
+/// [`run`], for [`BorrowedHandler`]s, whose events borrow from the event body instead of owning
+/// their data.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_borrowed<H: BorrowedHandler>(handler: H) -> ! {
+    let mut handler = handler;
+    default_runtime().run_loop(|runtime, context, mut body| {
+        let buf = runtime.read_body(&mut body)?;
+        let event = de::from_slice(&buf)?;
+        match handler.handle(event) {
+            Ok(response) => runtime.respond(&context.request_id, &response),
+            Err(err) => runtime.report_error(&context.request_id, &err),
+        }
     })
 }
+
+/// [`run`], for handlers that read the event body themselves.
+///
+/// The handler receives a [`Body`], which implements [`Read`](std::io::Read) over the event as it
+/// comes off the wire, so large events can be processed incrementally instead of being deserialized
+/// into memory all at once. The current [`Context`] is available from [`context()`].
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_reader<F, S, E>(handler: F) -> !
+where
+    F: FnMut(&mut Body) -> Result<S, E>,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    let mut handler = handler;
+    default_runtime().run_loop(|runtime, context, mut body| match handler(&mut body) {
+        Ok(response) => runtime.respond(&context.request_id, &response),
+        Err(err) => runtime.report_error(&context.request_id, &err),
+    })
+}
+
+/// [`run`], for handlers that drive deserialization of the event themselves.
+///
+/// The handler receives a [`serde_json::Deserializer`] reading the event body as it comes off the
+/// wire, so it can deserialize with a custom [`Visitor`](serde::de::Visitor) or
+/// [`DeserializeSeed`](serde::de::DeserializeSeed) (e.g. to process each record in a large array
+/// without collecting them into a `Vec`). The current [`Context`] is available from [`context()`].
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_deserializer<F, S, E>(handler: F) -> !
+where
+    F: FnMut(&mut serde_json::Deserializer<serde_json::de::IoRead<&mut Body>>) -> Result<S, E>,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    let mut handler = handler;
+    default_runtime().run_loop(|runtime, context, mut body| {
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut body);
+        match handler(&mut deserializer) {
+            Ok(response) => runtime.respond(&context.request_id, &response),
+            Err(err) => runtime.report_error(&context.request_id, &err),
+        }
+    })
+}
+
+/// [`run`], for handlers that write the response body themselves.
+///
+/// The handler receives the deserialized event and a [`ResponseWriter`], which implements
+/// [`Write`](std::io::Write) and streams whatever the handler writes to the runtime API, so large
+/// responses don't need to be held in memory as a single value. Whatever is written is sent as-is;
+/// if you want a JSON response, write JSON (e.g. with `serde_json::to_writer`).
+///
+/// If the handler returns an error before writing anything, the error is reported as usual. If it
+/// has already written part of the response, the error is reported in the response's trailers.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_writer<F, D, E>(handler: F) -> !
+where
+    F: FnMut(D, &mut ResponseWriter) -> Result<(), E>,
+    D: DeserializeOwned,
+    E: ErrorTypeName + 'static,
+{
+    let mut handler = handler;
+    default_runtime().run_loop(|runtime, context, body| {
+        let event = de::from_reader(body)?;
+        let mut writer = runtime.client().response_writer(&context.request_id);
+        match handler(event, &mut writer) {
+            _ if !crate::watchdog::claim() => Ok(()),
+            Ok(()) => writer.finish(),
+            Err(err) => {
+                if writer.fail(err.error_type_name(), &runtime.error_message(&err))? {
+                    runtime.check_recoverable(&err);
+                    Ok(())
+                } else {
+                    runtime.report_error(&context.request_id, &err)
+                }
+            }
+        }
+    })
+}
+
+/// Runs an [external extension][external], calling the handler with each event from the
+/// [Extensions API](extensions): an [`Invoke`](extensions::Event::Invoke) for each invocation of
+/// the function, and a [`Shutdown`](extensions::Event::Shutdown) before the execution environment
+/// shuts down.
+///
+/// ```rust,no_run
+/// use minlambda::extensions::Event;
+///
+/// fn main() -> ! {
+///     minlambda::run_extension(|event: Event| -> Result<(), std::io::Error> {
+///         if let Event::Invoke { request_id, .. } = event {
+///             eprintln!("invoked: {request_id}");
+///         }
+///         Ok(())
+///     })
+/// }
+/// ```
+///
+/// The extension registers for both kinds of event, with the file name of its executable (as
+/// Lambda requires). Once the handler returns from the shutdown event, the process exits. If the
+/// handler returns an error, it's reported as the extension's exit error and the process exits
+/// with a nonzero status, after which Lambda shuts the execution environment down.
+///
+/// Like `run`, this function does not return.
+///
+/// # Panics
+///
+/// This function panics if the extension can't be registered, or if getting an event or reporting
+/// the handler's error fails.
+///
+/// [external]: https://docs.aws.amazon.com/lambda/latest/dg/lambda-extensions.html
+pub fn run_extension<F, E>(handler: F) -> !
+where
+    F: FnMut(extensions::Event) -> Result<(), E>,
+    E: ErrorTypeName + 'static,
+{
+    let name =
+        extensions::executable_name().expect("could not get the extension's executable name");
+    let events = [
+        extensions::EventType::Invoke,
+        extensions::EventType::Shutdown,
+    ];
+    extensions::Extension::register(&name, &events)
+        .unwrap_or_else(|err| panic!("failed to register extension: {}", err))
+        .run(handler)
+}
+
+/// [`run`], for handlers that return futures, driven by the [`Executor`] of your choice.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_async_on<X, F, Fut, D, S, E>(executor: X, handler: F) -> !
+where
+    X: Executor,
+    F: FnMut(D) -> Fut,
+    Fut: std::future::Future<Output = Result<S, E>>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    let mut executor = executor;
+    let mut handler = handler;
+    run(|event: D| executor.block_on(handler(event)))
+}
+
+/// [`run`], for handlers that return futures, without an async runtime.
+///
+/// The handler's futures are polled to completion on the current thread by a tiny built-in
+/// executor ([`executor::BlockOn`]), which parks the thread while a future is pending. There is no
+/// I/O or timer driver, so futures that depend on a particular runtime (e.g. Tokio's networking
+/// types) will not make progress; use `run_async` (with the `tokio` feature) or [`run_async_on`]
+/// for those.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_future<F, Fut, D, S, E>(handler: F) -> !
+where
+    F: FnMut(D) -> Fut,
+    Fut: std::future::Future<Output = Result<S, E>>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    run_async_on(executor::BlockOn, handler)
+}
+
+/// [`run`], for async handlers.
+///
+/// The handler's futures are driven on a current-thread Tokio runtime created by this function,
+/// with whichever Tokio drivers (I/O, time) your dependencies have enabled.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+///
+/// # Panics
+///
+/// In addition to the panics described in `run`, this function panics if the Tokio runtime cannot
+/// be created.
+#[cfg(feature = "tokio")]
+pub fn run_async<F, Fut, D, S, E>(handler: F) -> !
+where
+    F: FnMut(D) -> Fut,
+    Fut: std::future::Future<Output = Result<S, E>>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("could not create Tokio runtime");
+    run_async_on(runtime, handler)
+}
+
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub fn __run_async_default<F, Fut, D, S, E>(handler: F) -> !
+where
+    F: FnMut(D) -> Fut,
+    Fut: std::future::Future<Output = Result<S, E>>,
+    D: DeserializeOwned,
+    S: Serialize,
+    E: ErrorTypeName + 'static,
+{
+    #[cfg(feature = "tokio")]
+    let run = run_async;
+    #[cfg(not(feature = "tokio"))]
+    let run = run_future;
+    run(handler)
+}
+
+pub(crate) fn default_runtime() -> Runtime {
+    Runtime::builder()
+        .build()
+        .unwrap_or_else(|err| panic!("{}", err))
+}
@@ -0,0 +1,118 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! A tiny HTTP server for the batches of events Lambda posts to an extension that has subscribed
+//! to them.
+//!
+//! It understands just the requests Lambda makes: a `POST` (or `PUT`) with a `Content-Length`,
+//! on a connection that may be kept open for the next batch. Each connection gets a thread of its
+//! own, so one left idle doesn't hold up the others.
+
+use crate::http::split_header;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The longest request or header line to accept.
+const MAX_LINE: usize = 8 * 1024;
+
+/// The largest batch to accept. Lambda's own limit on a batch is 1 MB, but the events are
+/// serialized after that's counted.
+const MAX_BODY: usize = 8 * 1024 * 1024;
+
+/// Accepts connections on `listener` on a thread named `name`, calling `handle` with the body of
+/// each request. `handle` returns whether it could make sense of the body, which is the
+/// difference between a 200 and a 400 response.
+pub(crate) fn spawn<F>(listener: TcpListener, name: &str, handle: F) -> io::Result<()>
+where
+    F: FnMut(&[u8]) -> bool + Send + 'static,
+{
+    let handle = Arc::new(Mutex::new(handle));
+    let name = String::from(name);
+    std::thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handle = Arc::clone(&handle);
+                // A connection that can't get a thread is dropped, and Lambda tries again.
+                let _ = std::thread::Builder::new()
+                    .name(name.clone())
+                    .spawn(move || serve(stream, &handle));
+            }
+        })?;
+    Ok(())
+}
+
+/// Answers requests on a connection until it's closed or something goes wrong with it.
+fn serve<F>(stream: TcpStream, handle: &Mutex<F>) -> io::Result<()>
+where
+    F: FnMut(&[u8]) -> bool,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut line = Vec::new();
+    let mut body = Vec::new();
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line)? == 0 {
+            return Ok(());
+        }
+        let mut len = None;
+        let mut close = line.ends_with(b"HTTP/1.0\r\n");
+        loop {
+            line.clear();
+            if read_line(&mut reader, &mut line)? == 0 {
+                return Ok(());
+            }
+            let Ok(header) = std::str::from_utf8(&line) else {
+                continue;
+            };
+            if header.trim().is_empty() {
+                break;
+            }
+            match split_header(header) {
+                Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                    len = value.parse::<usize>().ok();
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("connection") => {
+                    close = value.eq_ignore_ascii_case("close");
+                }
+                _ => {}
+            }
+        }
+        // Without reading past the body, there's no telling where the next request starts, so
+        // the connection is closed if it isn't read.
+        let (status, close) = match len {
+            None => ("411 Length Required", true),
+            Some(len) if len > MAX_BODY => ("413 Content Too Large", true),
+            Some(len) => {
+                body.resize(len, 0);
+                reader.read_exact(&mut body)?;
+                let ok = (handle.lock().unwrap_or_else(PoisonError::into_inner))(&body);
+                (if ok { "200 OK" } else { "400 Bad Request" }, close)
+            }
+        };
+        write!(
+            writer,
+            "HTTP/1.1 {status}\r\ncontent-length: 0\r\n{}\r\n",
+            if close { "connection: close\r\n" } else { "" }
+        )?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads a line into `line`, failing if it's longer than [`MAX_LINE`].
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> io::Result<usize> {
+    let limit = u64::try_from(MAX_LINE).map_or(u64::MAX, |max| max.saturating_add(1));
+    let len = reader.take(limit).read_until(b'\n', line)?;
+    if len > MAX_LINE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request line too long",
+        ));
+    }
+    Ok(len)
+}
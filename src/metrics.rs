@@ -0,0 +1,216 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Counters and histograms that accumulate across invocations, and are emitted once when the
+//! execution environment shuts down.
+//!
+//! Emitting metrics at the end of each invocation costs time in every invocation, and makes many
+//! small data points out of what could be one. Instead, [`emit_at_shutdown`] registers an internal
+//! extension, which has Lambda send the process `SIGTERM` before shutting the execution
+//! environment down, and hands everything recorded since the process started to a function then.
+//! minlambda doesn't depend on any particular metrics service; the function sends the metrics
+//! wherever it likes.
+//!
+//! ```rust,no_run
+//! use minlambda::metrics::{self, Metric, MetricValue};
+//!
+//! fn main() -> std::io::Result<()> {
+//!     metrics::emit_at_shutdown(|metrics: Vec<Metric>| {
+//!         for metric in metrics {
+//!             match metric.value {
+//!                 MetricValue::Counter(count) => println!("{}: {count}", metric.name),
+//!                 MetricValue::Histogram(summary) => println!("{}: {summary:?}", metric.name),
+//!                 _ => {}
+//!             }
+//!         }
+//!     })?;
+//!     minlambda::run_ok(|name: String| {
+//!         metrics::counter("greetings").increment();
+//!         metrics::histogram("name_length").record(name.len() as f64);
+//!         format!("Hello, {name}!")
+//!     })
+//! }
+//! ```
+//!
+//! This module is available with the `metrics` feature.
+
+use crate::error::Result;
+use crate::extensions::{EventType, Extension};
+use crate::http;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// How long the function passed to [`emit_at_shutdown`] gets. Lambda gives the process about
+/// half a second after `SIGTERM`.
+const EMIT_BUDGET: Duration = Duration::from_millis(300);
+
+/// How many times in a row the extension asks for the next event again after a transient error.
+const POLL_RETRIES: u32 = 5;
+
+static COUNTERS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+static HISTOGRAMS: Mutex<BTreeMap<&'static str, Summary>> = Mutex::new(BTreeMap::new());
+
+/// A counter, for recording how many times something happened.
+#[derive(Debug, Clone, Copy)]
+pub struct Counter(&'static str);
+
+impl Counter {
+    /// Adds `n` to the counter.
+    pub fn add(self, n: u64) {
+        let mut counters = lock(&COUNTERS);
+        let count = counters.entry(self.0).or_default();
+        *count = count.saturating_add(n);
+    }
+
+    /// Adds one to the counter.
+    pub fn increment(self) {
+        self.add(1);
+    }
+}
+
+/// A histogram, for recording the distribution of some value (such as how long something took).
+///
+/// Only a summary of the values recorded is kept: how many there were, their sum, and the
+/// smallest and largest.
+#[derive(Debug, Clone, Copy)]
+pub struct Histogram(&'static str);
+
+impl Histogram {
+    /// Records a value.
+    pub fn record(self, value: f64) {
+        let mut histograms = lock(&HISTOGRAMS);
+        let summary = histograms.entry(self.0).or_insert(Summary {
+            count: 0,
+            sum: 0.0,
+            min: value,
+            max: value,
+        });
+        summary.count += 1;
+        summary.sum += value;
+        summary.min = summary.min.min(value);
+        summary.max = summary.max.max(value);
+    }
+}
+
+/// Returns the counter named `name`.
+#[must_use]
+pub fn counter(name: &'static str) -> Counter {
+    Counter(name)
+}
+
+/// Returns the histogram named `name`.
+#[must_use]
+pub fn histogram(name: &'static str) -> Histogram {
+    Histogram(name)
+}
+
+/// A metric, as handed to the function passed to [`emit_at_shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Metric {
+    /// The metric's name.
+    pub name: &'static str,
+    /// The metric's value.
+    pub value: MetricValue,
+}
+
+/// The value of a [`Metric`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum MetricValue {
+    /// A counter's count.
+    Counter(u64),
+    /// A summary of the values recorded in a histogram.
+    Histogram(Summary),
+}
+
+/// A summary of the values recorded in a [`Histogram`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Summary {
+    /// How many values were recorded.
+    pub count: u64,
+    /// The sum of the values.
+    pub sum: f64,
+    /// The smallest value.
+    pub min: f64,
+    /// The largest value.
+    pub max: f64,
+}
+
+/// Returns everything recorded so far, counters first, and resets it all.
+///
+/// [`emit_at_shutdown`] uses this; it's available for emitting metrics some other way.
+#[must_use]
+pub fn take() -> Vec<Metric> {
+    let counters = std::mem::take(&mut *lock(&COUNTERS));
+    let histograms = std::mem::take(&mut *lock(&HISTOGRAMS));
+    let counters = (counters.into_iter()).map(|(name, count)| Metric {
+        name,
+        value: MetricValue::Counter(count),
+    });
+    let histograms = (histograms.into_iter()).map(|(name, summary)| Metric {
+        name,
+        value: MetricValue::Histogram(summary),
+    });
+    counters.chain(histograms).collect()
+}
+
+/// Calls `emit` with the metrics recorded (see [`take`]) when the execution environment shuts
+/// down.
+///
+/// This registers an internal extension named `minlambda-metrics`, which has to happen before the
+/// runtime asks for its first invocation, so call this before [`run`](crate::run) or the like.
+/// `emit` is called once, from [`on_shutdown`](crate::on_shutdown) with a budget of 300 ms;
+/// metrics recorded after that (or in a process that exits some other way) are lost. If the
+/// extension can't get its events from Lambda, even after retrying, it reports an error and the
+/// process exits, rather than leave invocations waiting on it.
+///
+/// # Errors
+///
+/// Returns an error if the extension can't be registered.
+///
+/// # Panics
+///
+/// Panics if a `SIGTERM` handler can't be registered.
+pub fn emit_at_shutdown<F>(emit: F) -> Result<()>
+where
+    F: FnOnce(Vec<Metric>) + Send + 'static,
+{
+    let extension = Extension::register("minlambda-metrics", &[EventType::Invoke])?;
+    // Lambda waits for every extension to ask for the next event before finishing an invocation,
+    // so the extension asks right away each time.
+    std::thread::Builder::new()
+        .name(String::from("minlambda-metrics"))
+        .spawn(move || poll(&extension))?;
+    crate::on_shutdown(EMIT_BUDGET, move || emit(take()));
+    Ok(())
+}
+
+/// Asks for each extension event as soon as the last one arrives. If that keeps failing, the
+/// process exits: Lambda would otherwise wait on the extension, until the function times out, in
+/// every invocation after.
+fn poll(extension: &Extension) -> ! {
+    let mut retries = 0;
+    loop {
+        match extension.next_event() {
+            Ok(_) => retries = 0,
+            Err(err) if retries < POLL_RETRIES && err.is_transient() => {
+                std::thread::sleep(http::backoff(retries));
+                retries += 1;
+            }
+            Err(err) => {
+                eprintln!("minlambda-metrics: failed to get the next extension event: {err}");
+                // Lambda shuts the execution environment down after this, and a new one starts.
+                let _ = extension.report_exit_error(&err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
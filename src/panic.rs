@@ -0,0 +1,87 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::error;
+use crate::http::{self, Endpoint};
+use crate::watchdog;
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::PanicHookInfo;
+use std::sync::{Mutex, PoisonError};
+
+thread_local! {
+    /// Whether this thread runs the handler, so its panics should be reported.
+    static HANDLER_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The message of the last panic during an invocation, kept until the runtime knows whether the
+/// handler caught it.
+static UNWINDING: Mutex<Option<String>> = Mutex::new(None);
+
+/// Installs a panic hook that reports panics on the current thread (and any marked with
+/// [`mark_handler_thread`]) to the runtime API, after running the previous hook. Panics on other
+/// threads are left alone.
+///
+/// If the process aborts on panic, panics are reported from the hook, since nothing else gets the
+/// chance: as the invocation's error during an invocation, or as an initialization error
+/// otherwise. If panics unwind, a panic during an invocation is reported by the runtime once it
+/// unwinds out of the handler (see [`report_unwound`]), so panics that the handler catches aren't
+/// reported; panics outside of an invocation are still reported from the hook, even if they're
+/// caught.
+pub(crate) fn install(endpoint: Endpoint) {
+    mark_handler_thread();
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        if !HANDLER_THREAD.with(Cell::get) {
+            return;
+        }
+        let message = message(info);
+        if cfg!(panic = "abort") || crate::context().is_none() {
+            report(&endpoint, &message);
+        } else {
+            *UNWINDING.lock().unwrap_or_else(PoisonError::into_inner) = Some(message);
+        }
+    }));
+}
+
+/// Has the panic hook report panics on the current thread, such as one the handler is called on.
+pub(crate) fn mark_handler_thread() {
+    HANDLER_THREAD.with(|handler_thread| handler_thread.set(true));
+}
+
+/// Reports a panic that unwound out of the handler.
+pub(crate) fn report_unwound(endpoint: &Endpoint, payload: &(dyn Any + Send)) {
+    let message = (UNWINDING.lock().unwrap_or_else(PoisonError::into_inner))
+        .take()
+        .unwrap_or_else(|| error::truncate(format!("panicked: {}", payload_str(payload))));
+    report(endpoint, &message);
+}
+
+/// Reports a panic as the current invocation's error, unless something else has already claimed
+/// the invocation, or as an initialization error if there's no current invocation.
+fn report(endpoint: &Endpoint, message: &str) {
+    let path = match crate::context() {
+        Some(context) if watchdog::claim_for_panic() => {
+            format!("invocation/{}/error", context.request_id)
+        }
+        Some(_) => return,
+        None => String::from("init/error"),
+    };
+    // There's no one to tell if this fails; Lambda will report the process exiting.
+    let _ = http::post_error(endpoint, &path, "minlambda::Panic", message);
+}
+
+pub(crate) fn message(info: &PanicHookInfo<'_>) -> String {
+    let payload = payload_str(info.payload());
+    error::truncate(match info.location() {
+        Some(location) => format!("panicked at {location}: {payload}"),
+        None => format!("panicked: {payload}"),
+    })
+}
+
+fn payload_str(payload: &(dyn Any + Send)) -> &str {
+    (payload.downcast_ref::<&str>().copied())
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
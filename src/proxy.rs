@@ -0,0 +1,266 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Reaching servers other than the runtime API through an HTTP proxy, as functions in a VPC
+//! without a NAT gateway may need to.
+//!
+//! The proxy is taken from the `HTTPS_PROXY` (or `https_proxy`) environment variable for HTTPS
+//! servers, and `HTTP_PROXY` (or `http_proxy`) for plain HTTP ones, unless the host is listed in
+//! `NO_PROXY` (or `no_proxy`). Host names there match themselves and their subdomains, IP
+//! addresses match themselves, CIDR blocks like `169.254.0.0/16` match the addresses in them, and
+//! `*` matches everything. HTTPS connections are tunneled with `CONNECT`, so the proxy never sees
+//! what's sent over them; plain HTTP requests are sent to the proxy with the server's URL as their
+//! target, since many proxies only allow `CONNECT` to port 443.
+
+use crate::base64;
+use crate::error::{Error, Result};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::sync::Arc;
+
+/// The longest `CONNECT` response head to read.
+const MAX_HEAD: usize = 8 * 1024;
+
+/// An HTTP proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Proxy {
+    /// The proxy's host and port, for connecting to it.
+    pub(crate) addr: Arc<str>,
+    /// The value of the `Proxy-Authorization` header, if the proxy URL had credentials.
+    auth: Option<Arc<str>>,
+}
+
+impl Proxy {
+    /// Returns the proxy to reach `host` through, if there is one, given whether it's reached with
+    /// `tls`.
+    ///
+    /// The proxy URL can be `http://[user:password@]host[:port]`, or just the host and port;
+    /// the port defaults to 80.
+    pub(crate) fn from_env(host: &str, tls: bool) -> Result<Option<Proxy>> {
+        let name = if tls { "HTTPS_PROXY" } else { "HTTP_PROXY" };
+        let Some(url) = var(name, &name.to_ascii_lowercase()) else {
+            return Ok(None);
+        };
+        if var("NO_PROXY", "no_proxy").is_some_and(|list| no_proxy(&list, host)) {
+            return Ok(None);
+        }
+        let rest = match url.split_once("://") {
+            None => url.as_str(),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) => {
+                return Err(Error::Config(format!(
+                    "unsupported proxy scheme {scheme:?} in ${name}"
+                )));
+            }
+        };
+        let authority = rest.split('/').next().unwrap_or_default();
+        let (auth, addr) = match authority.rsplit_once('@') {
+            Some((userinfo, addr)) => (
+                Some(Arc::from(format!(
+                    "Basic {}",
+                    base64::encode(&percent_decode(userinfo))
+                ))),
+                addr,
+            ),
+            None => (None, authority),
+        };
+        if addr.is_empty() {
+            return Err(Error::Config(format!(
+                "could not parse ${name} ({url:?}) as a proxy URL"
+            )));
+        }
+        // A port is there unless the authority ends with a bracketed IPv6 address or has no colon.
+        let addr = match addr.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => Arc::from(addr),
+            _ => Arc::from(format!("{addr}:80")),
+        };
+        Ok(Some(Proxy { addr, auth }))
+    }
+
+    /// Returns the value of the `Proxy-Authorization` header, if the proxy URL had credentials.
+    pub(crate) fn auth(&self) -> Option<&str> {
+        self.auth.as_deref()
+    }
+
+    /// Asks the proxy, connected to with `stream`, to tunnel it to `host` and `port`.
+    pub(crate) fn tunnel(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let mut request = Vec::new();
+        write!(
+            request,
+            "CONNECT {host}:{port} HTTP/1.1\r\nhost: {host}:{port}\r\n"
+        )?;
+        if let Some(auth) = &self.auth {
+            write!(request, "proxy-authorization: {auth}\r\n")?;
+        }
+        request.extend_from_slice(b"\r\n");
+        stream.write_all(&request)?;
+
+        // What comes after the response head belongs to the tunnel, so it's read a byte at a
+        // time rather than buffered. It's short.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = 0;
+            stream.read_exact(std::slice::from_mut(&mut byte))?;
+            head.push(byte);
+            if head.len() > MAX_HEAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "proxy response too long",
+                ));
+            }
+        }
+        let status = (head
+            .strip_prefix(b"HTTP/1.1 ")
+            .or_else(|| head.strip_prefix(b"HTTP/1.0 ")))
+        .and_then(|rest| rest.get(..3))
+        .and_then(|status| std::str::from_utf8(status).ok())
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed proxy response"))?;
+        if !(200..300).contains(&status) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("proxy refused to connect to {host}:{port} (status {status})"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the value of the first of two environment variables that's set and not empty.
+fn var(name: &str, alt: &str) -> Option<String> {
+    (std::env::var(name).ok())
+        .filter(|value| !value.is_empty())
+        .or_else(|| std::env::var(alt).ok().filter(|value| !value.is_empty()))
+}
+
+/// Decodes the `%XX` escapes in a proxy URL's user name and password.
+fn percent_decode(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let [byte, tail @ ..] = rest {
+        let escaped = (tail.get(..2))
+            .filter(|hex| *byte == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(escaped) = escaped {
+            bytes.push(escaped);
+            rest = &tail[2..];
+        } else {
+            bytes.push(*byte);
+            rest = tail;
+        }
+    }
+    bytes
+}
+
+/// Returns whether `host` is matched by a `NO_PROXY` list.
+fn no_proxy(list: &str, host: &str) -> bool {
+    let host = unbracket(host).to_ascii_lowercase();
+    let ip = host.parse::<IpAddr>().ok();
+    list.split(',')
+        .map(|entry| {
+            entry
+                .trim()
+                .trim_start_matches("*.")
+                .trim_start_matches('.')
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_ascii_lowercase)
+        .any(|entry| {
+            entry == "*"
+                || ip.map_or_else(
+                    || {
+                        host == entry
+                            || (host.strip_suffix(&entry)).is_some_and(|rest| rest.ends_with('.'))
+                    },
+                    |ip| in_block(&entry, ip),
+                )
+        })
+}
+
+/// Returns whether `ip` is the address, or in the CIDR block, of a `NO_PROXY` entry.
+fn in_block(entry: &str, ip: IpAddr) -> bool {
+    let (network, prefix) = match entry.split_once('/') {
+        Some((network, prefix)) => match prefix.parse::<u32>() {
+            Ok(prefix) => (network, Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (entry, None),
+    };
+    match (unbracket(network).parse::<IpAddr>(), ip) {
+        (Ok(IpAddr::V4(network)), IpAddr::V4(ip)) => {
+            let prefix = prefix.unwrap_or(32);
+            let mask = u32::MAX.checked_shl(32 - prefix.min(32)).unwrap_or(0);
+            prefix <= 32 && u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (Ok(IpAddr::V6(network)), IpAddr::V6(ip)) => {
+            let prefix = prefix.unwrap_or(128);
+            let mask = u128::MAX.checked_shl(128 - prefix.min(128)).unwrap_or(0);
+            prefix <= 128 && u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Strips the brackets from an IPv6 address in a URL.
+fn unbracket(host: &str) -> &str {
+    (host.strip_prefix('['))
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{no_proxy, percent_decode};
+
+    #[test]
+    fn no_proxy_names() {
+        let list = "example.com, .internal,*.amazonaws.com";
+        assert!(no_proxy(list, "example.com"));
+        assert!(no_proxy(list, "API.Example.com"));
+        assert!(no_proxy(list, "db.internal"));
+        assert!(no_proxy(list, "internal"));
+        assert!(no_proxy(list, "s3.us-east-1.amazonaws.com"));
+        assert!(!no_proxy(list, "notexample.com"));
+        assert!(!no_proxy(list, "example.org"));
+        assert!(!no_proxy("", "example.com"));
+        assert!(!no_proxy(" , ", "example.com"));
+        assert!(no_proxy("localhost,*", "example.com"));
+    }
+
+    #[test]
+    fn no_proxy_addresses() {
+        let list = "169.254.0.0/16,10.1.2.3,fd00::/8,[::1]";
+        assert!(no_proxy(list, "169.254.169.254"));
+        assert!(no_proxy(list, "10.1.2.3"));
+        assert!(!no_proxy(list, "10.1.2.4"));
+        assert!(!no_proxy(list, "169.255.0.1"));
+        assert!(no_proxy(list, "[fd12:3456::1]"));
+        assert!(no_proxy(list, "fd12:3456::1"));
+        assert!(no_proxy(list, "[::1]"));
+        assert!(!no_proxy(list, "[fe80::1]"));
+        assert!(!no_proxy(list, "169.254.example.com"));
+        assert!(no_proxy("0.0.0.0/0", "203.0.113.9"));
+        assert!(!no_proxy("0.0.0.0/0", "::1"));
+        assert!(no_proxy("::/0", "2001:db8::1"));
+        assert!(no_proxy("192.0.2.1/32", "192.0.2.1"));
+        assert!(!no_proxy("192.0.2.0/33", "192.0.2.1"));
+        assert!(!no_proxy("192.0.2.0/x", "192.0.2.1"));
+    }
+
+    #[test]
+    fn percent_decode_escapes() {
+        assert_eq!(percent_decode("user:p%40ss%3Aword"), b"user:p@ss:word");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}".as_bytes());
+        assert_eq!(percent_decode("plain"), b"plain");
+        assert_eq!(percent_decode(""), b"");
+    }
+
+    #[test]
+    fn percent_decode_malformed() {
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%4"), b"%4");
+        assert_eq!(percent_decode("%zz%41"), b"%zzA");
+        assert_eq!(percent_decode("%+1"), b"%+1");
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::router::{erase, Route};
+use crate::{Handler, RouteError};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+
+/// A set of named handlers, one of which is selected at startup.
+///
+/// This mirrors how managed runtimes dispatch container image deployments: the handler named by
+/// the function's `_HANDLER` environment variable (the image's `CMD`, or the function's handler
+/// setting) is the one that runs.
+///
+/// ```rust,no_run
+/// fn main() -> ! {
+///     let mut registry = minlambda::Registry::new();
+///     registry.insert("orders.process", |order: serde_json::Value| {
+///         Ok::<_, String>(order)
+///     });
+///     registry.insert("orders.refund", |_: serde_json::Value| Ok::<_, String>(()));
+///     minlambda::run_registry(registry)
+/// }
+/// ```
+#[derive(Default)]
+pub struct Registry {
+    pub(crate) handlers: BTreeMap<String, Route>,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    #[must_use]
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Registers a handler under `name`, replacing any handler previously registered under it.
+    pub fn insert<H, D>(&mut self, name: impl Into<String>, handler: H) -> &mut Registry
+    where
+        H: Handler<D> + 'static,
+        D: DeserializeOwned,
+    {
+        self.handlers.insert(name.into(), erase(handler));
+        self
+    }
+
+    pub(crate) fn names(&self) -> String {
+        let names: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        names.join(", ")
+    }
+
+    /// Returns whether a handler is registered for the function `name`, with or without a
+    /// qualifier.
+    fn serves(&self, name: &str) -> bool {
+        let qualified_prefix = format!("{name}:");
+        (self.handlers.keys()).any(|key| *key == name || key.starts_with(&qualified_prefix))
+    }
+
+    /// Returns the handler for an invocation of `arn`, an ARN of the function `name`: the one
+    /// registered as `name:qualifier` if there is one, otherwise the one registered as `name`.
+    fn for_invocation(&mut self, name: &str, arn: &str) -> Option<&mut Route> {
+        // The invoked ARN is `arn:aws:lambda:<region>:<account>:function:<name>[:<qualifier>]`.
+        let qualified = (arn.splitn(8, ':').nth(7)).map(|qualifier| format!("{name}:{qualifier}"));
+        let key = match qualified {
+            Some(key) if self.handlers.contains_key(&key) => key,
+            _ => name.to_owned(),
+        };
+        self.handlers.get_mut(&key)
+    }
+}
+
+impl Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("handlers", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// [`run`](crate::run), with the handler in `registry` named by the `_HANDLER` environment
+/// variable.
+///
+/// If `_HANDLER` is not set or doesn't name a registered handler, an initialization error is
+/// reported and the process exits.
+///
+/// # Panics
+///
+/// This function panics on the same fatal error conditions as `run`.
+pub fn run_registry(registry: Registry) -> ! {
+    let runtime = crate::default_runtime();
+    let mut registry = registry;
+    let name = std::env::var("_HANDLER").unwrap_or_default();
+    match registry.handlers.remove(&name) {
+        Some(handler) => runtime.run(handler),
+        None => runtime.init_failed(
+            "minlambda::Error",
+            &format!(
+                "no handler named {:?} (registered handlers: {})",
+                name,
+                registry.names()
+            ),
+        ),
+    }
+}
+
+/// [`run`](crate::run), with the handler in `registry` named after the function being invoked.
+///
+/// This lets one binary be deployed as several functions. Each invocation is dispatched to the
+/// handler registered as `name:qualifier` (for example, `orders-api:live` for the `live` alias or
+/// `orders-api:7` for version 7) if there is one, otherwise to the handler registered as the
+/// function's name, `AWS_LAMBDA_FUNCTION_NAME`. Invocations with no matching handler are reported
+/// as errors.
+///
+/// If no handler is registered for the function at all, an initialization error is reported and
+/// the process exits.
+///
+/// # Panics
+///
+/// This function panics on the same fatal error conditions as `run`.
+pub fn run_by_function_name(registry: Registry) -> ! {
+    let runtime = crate::default_runtime();
+    let mut registry = registry;
+    let name = std::env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap_or_default();
+    if !registry.serves(&name) {
+        runtime.init_failed(
+            "minlambda::Error",
+            &format!(
+                "no handler for function {:?} (registered handlers: {})",
+                name,
+                registry.names()
+            ),
+        );
+    }
+
+    runtime.run_with_context(move |context, event| {
+        match registry.for_invocation(&name, &context.invoked_function_arn) {
+            Some(handler) => handler(event),
+            None => Err(RouteError::unrouted(format!(
+                "no handler for {:?}",
+                context.invoked_function_arn
+            ))),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+    use crate::MaybeEmpty;
+    use serde_json::Value;
+
+    fn registry() -> Registry {
+        let mut registry = Registry::new();
+        registry
+            .insert("orders", |_: Value| Ok::<_, String>("replaced"))
+            .insert("orders", |_: Value| Ok::<_, String>("orders"))
+            .insert("orders:live", |_: Value| Ok::<_, String>("orders:live"))
+            .insert("refunds:7", |_: Value| Ok::<_, String>("refunds:7"));
+        registry
+    }
+
+    #[test]
+    fn insert() {
+        let mut registry = registry();
+        assert_eq!(registry.names(), "orders, orders:live, refunds:7");
+        let handler = registry.handlers.get_mut("orders").unwrap();
+        assert_eq!(
+            handler(Value::Null).unwrap(),
+            MaybeEmpty(Some(Value::from("orders")))
+        );
+    }
+
+    #[test]
+    fn serves() {
+        assert!(registry().serves("orders"));
+        assert!(registry().serves("refunds"));
+        assert!(!registry().serves("refund"));
+    }
+
+    #[test]
+    fn for_invocation() {
+        let mut registry = registry();
+        let mut handle = |name: &str, qualified: &str| {
+            let arn = format!("arn:aws:lambda:us-east-1:123456789012:function:{qualified}");
+            let handler = registry.for_invocation(name, &arn)?;
+            match handler(Value::Null).unwrap() {
+                MaybeEmpty(Some(Value::String(response))) => Some(response),
+                response => panic!("unexpected response {:?}", response),
+            }
+        };
+        assert_eq!(handle("orders", "orders").as_deref(), Some("orders"));
+        assert_eq!(
+            handle("orders", "orders:live").as_deref(),
+            Some("orders:live")
+        );
+        assert_eq!(handle("orders", "orders:7").as_deref(), Some("orders"));
+        assert_eq!(handle("refunds", "refunds:7").as_deref(), Some("refunds:7"));
+        assert_eq!(handle("refunds", "refunds:live"), None);
+        assert_eq!(handle("refunds", "refunds"), None);
+    }
+}
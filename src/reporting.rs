@@ -0,0 +1,143 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Sending errors and panics to an error tracker.
+//!
+//! With a [`Reporter`] set by [`Builder::reporter`](crate::Builder::reporter), each error reported
+//! for an invocation and each panic is also formatted as an [`Event`] and handed to the reporter,
+//! which sends it wherever it likes. minlambda doesn't depend on any particular error tracker; a
+//! reporter for Sentry, for instance, would convert the event into a `sentry_core` event and
+//! capture it with the current hub.
+//!
+//! ```rust,no_run
+//! use minlambda::reporting::{self, Event};
+//!
+//! fn main() -> std::io::Result<()> {
+//!     minlambda::Runtime::builder()
+//!         .reporter(|event: &Event| eprintln!("would send: {event:?}"))
+//!         .build()?
+//!         .run(|name: String| {
+//!             reporting::add_breadcrumb(format!("greeting {name}"));
+//!             Ok::<_, String>(format!("Hello, {name}!"))
+//!         })
+//! }
+//! ```
+//!
+//! This module is available with the `reporting` feature.
+
+use crate::error::ErrorReport;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// How many breadcrumbs are kept for each invocation; older ones are dropped first.
+const MAX_BREADCRUMBS: usize = 100;
+
+static BREADCRUMBS: Mutex<VecDeque<Breadcrumb>> = Mutex::new(VecDeque::new());
+
+/// Something that sends [`Event`]s to an error tracker.
+///
+/// This is implemented for closures. Reporters are called on whichever thread the error or panic
+/// happened on, before the error is reported to the runtime API (or the panicking process exits),
+/// so they should send events synchronously.
+pub trait Reporter: Send + Sync {
+    /// Sends an event.
+    fn report(&self, event: &Event);
+}
+
+impl<F: Fn(&Event) + Send + Sync> Reporter for F {
+    fn report(&self, event: &Event) {
+        self(event);
+    }
+}
+
+/// What an [`Event`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// An error reported for an invocation.
+    Error,
+    /// A panic.
+    Panic,
+}
+
+/// An error or panic to send to an error tracker.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Event {
+    /// Whether this is an error or a panic.
+    pub kind: EventKind,
+    /// The error type, as reported to the runtime API; for panics, `minlambda::Panic`.
+    pub error_type: String,
+    /// The error message, including its source chain, as reported to the runtime API.
+    pub error_message: String,
+    /// The messages of each error in the error's source chain, starting with the error itself.
+    pub chain: Vec<String>,
+    /// The request ID of the invocation being handled, if any.
+    pub request_id: Option<String>,
+    /// The function version from the `AWS_LAMBDA_FUNCTION_VERSION` environment variable.
+    pub release: Option<String>,
+    /// The breadcrumbs recorded during the invocation, oldest first.
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+impl Event {
+    pub(crate) fn new(kind: EventKind, report: &ErrorReport<'_>, chain: Vec<String>) -> Event {
+        Event {
+            kind,
+            error_type: report.error_type.to_owned(),
+            error_message: report.error_message.to_owned(),
+            chain,
+            request_id: crate::context().map(|context| context.request_id),
+            release: std::env::var("AWS_LAMBDA_FUNCTION_VERSION").ok(),
+            breadcrumbs: (BREADCRUMBS.lock().unwrap_or_else(PoisonError::into_inner))
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Something that happened during an invocation, leading up to an error.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Breadcrumb {
+    /// When the breadcrumb was added.
+    pub timestamp: SystemTime,
+    /// What happened.
+    pub message: String,
+}
+
+/// Records a breadcrumb to include in events for the current invocation, such as from a logging
+/// layer.
+///
+/// Breadcrumbs are cleared when each invocation starts, and only the latest 100 are kept.
+pub fn add_breadcrumb<M: Into<String>>(message: M) {
+    let mut breadcrumbs = BREADCRUMBS.lock().unwrap_or_else(PoisonError::into_inner);
+    if breadcrumbs.len() == MAX_BREADCRUMBS {
+        breadcrumbs.pop_front();
+    }
+    breadcrumbs.push_back(Breadcrumb {
+        timestamp: SystemTime::now(),
+        message: message.into(),
+    });
+}
+
+pub(crate) fn clear_breadcrumbs() {
+    (BREADCRUMBS.lock().unwrap_or_else(PoisonError::into_inner)).clear();
+}
+
+/// Installs a panic hook that sends panics to `reporter`, after running the previous hook.
+pub(crate) fn install(reporter: Arc<dyn Reporter>) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        let message = crate::panic::message(info);
+        let report = ErrorReport::new("minlambda::Panic", &message);
+        reporter.report(&Event::new(
+            EventKind::Panic,
+            &report,
+            vec![message.clone()],
+        ));
+    }));
+}
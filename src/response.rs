@@ -0,0 +1,233 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use serde::ser::{Error as _, Impossible, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::fmt::{self, Display};
+use std::string::FromUtf8Error;
+
+/// A response that is already serialized JSON, written to the runtime API as-is.
+///
+/// This is useful for handlers that proxy JSON from elsewhere, which would otherwise need to parse
+/// it into a [`serde_json::Value`] only to serialize it again. The contents are checked to be
+/// valid JSON, but not parsed into anything; if they aren't valid, the response fails to serialize
+/// and that error is reported instead.
+///
+/// ```rust,no_run
+/// fn main() -> ! {
+///     minlambda::run(|_: serde_json::Value| {
+///         Ok::<_, std::convert::Infallible>(minlambda::RawJson(String::from(r#"{"ok":true}"#)))
+///     })
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RawJson(pub String);
+
+impl RawJson {
+    /// Wraps serialized JSON in a byte vector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `json` is not valid UTF-8.
+    pub fn from_utf8(json: Vec<u8>) -> Result<RawJson, FromUtf8Error> {
+        String::from_utf8(json).map(RawJson)
+    }
+}
+
+impl From<String> for RawJson {
+    fn from(json: String) -> RawJson {
+        RawJson(json)
+    }
+}
+
+impl From<&str> for RawJson {
+    fn from(json: &str) -> RawJson {
+        RawJson(json.to_owned())
+    }
+}
+
+impl Serialize for RawJson {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // `serde_json` serializes a newtype struct as its contents, while checking whether the
+        // response is empty stops at the newtype struct, before the contents are validated.
+        serializer.serialize_newtype_struct("RawJson", &Unvalidated(&self.0))
+    }
+}
+
+/// The contents of a [`RawJson`], validated as they're serialized.
+struct Unvalidated<'a>(&'a str);
+
+impl Serialize for Unvalidated<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // `serde_json`'s serializer writes a `RawValue` out verbatim. Borrowing one from the string
+        // checks that it's valid JSON without copying it.
+        let raw: &RawValue = serde_json::from_str(self.0).map_err(S::Error::custom)?;
+        raw.serialize(serializer)
+    }
+}
+
+/// A response with no body.
+///
+/// Returning this from a handler posts an empty response instead of the JSON literal `null`.
+/// Other responses that serialize as `null`, such as `()`, [`serde_json::Value::Null`], and `None`,
+/// are still posted as `null`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NoResponse;
+
+/// A response that may be empty, as returned by handlers that wrap others, like
+/// [`Router`](crate::Router) and [`Layered`](crate::Layered). `Some` response is posted as usual,
+/// and `None` is posted as an empty body, like [`NoResponse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct MaybeEmpty<T>(pub Option<T>);
+
+impl<T: Serialize> Serialize for MaybeEmpty<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.0 {
+            Some(response) => response.serialize(serializer),
+            None => NoResponse.serialize(serializer),
+        }
+    }
+}
+
+/// The name [`NoResponse`] serializes with, which [`is_empty`] looks for.
+const NO_RESPONSE: &str = "minlambda::NoResponse";
+
+impl Serialize for NoResponse {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit_struct(NO_RESPONSE)
+    }
+}
+
+/// Returns whether a response should be posted as an empty body, which it is only if it's a
+/// [`NoResponse`].
+pub(crate) fn is_empty<S: Serialize + ?Sized>(response: &S) -> bool {
+    response.serialize(EmptyProbe).is_ok()
+}
+
+/// A serializer that succeeds only for [`NoResponse`].
+struct EmptyProbe;
+
+#[derive(Debug)]
+struct NotEmpty;
+
+impl Display for NotEmpty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("response is not empty")
+    }
+}
+
+impl std::error::Error for NotEmpty {}
+
+impl serde::ser::Error for NotEmpty {
+    fn custom<T: Display>(_msg: T) -> NotEmpty {
+        NotEmpty
+    }
+}
+
+macro_rules! not_empty {
+    ($($method:ident($($ty:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<$ok, NotEmpty> {
+                Err(NotEmpty)
+            }
+        )*
+    };
+}
+
+impl Serializer for EmptyProbe {
+    type Ok = ();
+    type Error = NotEmpty;
+    type SerializeSeq = Impossible<(), NotEmpty>;
+    type SerializeTuple = Impossible<(), NotEmpty>;
+    type SerializeTupleStruct = Impossible<(), NotEmpty>;
+    type SerializeTupleVariant = Impossible<(), NotEmpty>;
+    type SerializeMap = Impossible<(), NotEmpty>;
+    type SerializeStruct = Impossible<(), NotEmpty>;
+    type SerializeStructVariant = Impossible<(), NotEmpty>;
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), NotEmpty> {
+        if name == NO_RESPONSE {
+            Ok(())
+        } else {
+            Err(NotEmpty)
+        }
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<(), NotEmpty> {
+        Err(NotEmpty)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), NotEmpty> {
+        Err(NotEmpty)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), NotEmpty> {
+        Err(NotEmpty)
+    }
+
+    not_empty! {
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_unit() -> ();
+        serialize_none() -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_empty, MaybeEmpty, NoResponse, RawJson};
+    use serde_json::Value;
+
+    #[test]
+    fn only_no_response_is_empty() {
+        assert!(is_empty(&NoResponse));
+        assert!(!is_empty(&()));
+        assert!(!is_empty(&Value::Null));
+        assert!(!is_empty(&None::<u8>));
+        assert!(!is_empty(&Some(NoResponse)));
+        assert!(!is_empty("NoResponse"));
+        assert!(is_empty(&MaybeEmpty::<u8>(None)));
+        assert!(!is_empty(&MaybeEmpty(Some(()))));
+        assert_eq!(serde_json::to_string(&NoResponse).unwrap(), "null");
+    }
+
+    #[test]
+    fn raw_json() {
+        let raw = RawJson::from(r#"{"ok": [true, 1.50]}"#);
+        assert_eq!(serde_json::to_string(&raw).unwrap(), raw.0);
+        assert_eq!(serde_json::to_value(&raw).unwrap()["ok"][0], true);
+        assert!(!is_empty(&raw));
+        assert!(serde_json::to_string(&RawJson::from("{")).is_err());
+        assert!(!is_empty(&RawJson::from("{")));
+    }
+}
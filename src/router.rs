@@ -0,0 +1,267 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::{ErrorTypeName, Handler, MaybeEmpty};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt::{self, Debug, Display};
+
+pub(crate) type Route = Box<dyn FnMut(Value) -> Result<MaybeEmpty<Value>, RouteError>>;
+
+/// The kind of service that sent an invocation event, as detected by [`EventSource::detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EventSource {
+    /// An Amazon SQS message batch (`Records[].eventSource` is `aws:sqs`).
+    Sqs,
+    /// An Amazon API Gateway REST or HTTP API request (or a function URL request, which uses the
+    /// same shape as HTTP APIs).
+    ApiGateway,
+    /// An Amazon EventBridge (CloudWatch Events) event.
+    EventBridge,
+    /// Anything else.
+    Raw,
+}
+
+impl EventSource {
+    /// Guesses the source of an event from its shape.
+    #[must_use]
+    pub fn detect(event: &Value) -> EventSource {
+        let records_source = event
+            .get("Records")
+            .and_then(|records| records.get(0))
+            .and_then(|record| record.get("eventSource"))
+            .and_then(Value::as_str);
+        if records_source == Some("aws:sqs") {
+            EventSource::Sqs
+        } else if event.get("httpMethod").is_some()
+            || event.pointer("/requestContext/http/method").is_some()
+        {
+            EventSource::ApiGateway
+        } else if event.get("detail-type").is_some()
+            && event.get("source").is_some()
+            && event.get("detail").is_some()
+        {
+            EventSource::EventBridge
+        } else {
+            EventSource::Raw
+        }
+    }
+}
+
+/// Returns whether an event is a warm-up ping sent by [`serverless-plugin-warmup`][plugin], for use
+/// with [`Builder::warmup`](crate::Builder::warmup).
+///
+/// [plugin]: https://github.com/juanjoDiaz/serverless-plugin-warmup
+#[must_use]
+pub fn is_serverless_warmup(event: &Value) -> bool {
+    event.get("source").and_then(Value::as_str) == Some("serverless-plugin-warmup")
+}
+
+/// A handler that dispatches events to other handlers based on their [`EventSource`].
+///
+/// This is useful for a function deployed behind several triggers:
+///
+/// ```rust,no_run
+/// use minlambda::{EventSource, Router};
+/// use serde_json::Value;
+///
+/// fn main() -> ! {
+///     minlambda::run(
+///         Router::new()
+///             .route(EventSource::Sqs, |batch: Value| Ok::<_, String>(batch["Records"].clone()))
+///             .route(EventSource::Raw, |event: Value| Ok::<_, String>(event)),
+///     )
+/// }
+/// ```
+///
+/// Each route's handler deserializes the event into its own type. Events from a source without a
+/// route are reported as errors.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(EventSource, Route)>,
+}
+
+impl Router {
+    /// Creates a router with no routes.
+    #[must_use]
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Adds a handler for events from `source`. If a source is routed more than once, the first
+    /// route wins.
+    #[must_use]
+    pub fn route<H, D>(mut self, source: EventSource, handler: H) -> Router
+    where
+        H: Handler<D> + 'static,
+        D: DeserializeOwned,
+    {
+        self.routes.push((source, erase(handler)));
+        self
+    }
+}
+
+/// Boxes a handler into one that works with any JSON value.
+pub(crate) fn erase<H, D>(handler: H) -> Route
+where
+    H: Handler<D> + 'static,
+    D: DeserializeOwned,
+{
+    let mut handler = handler;
+    Box::new(move |event| {
+        let event = crate::de::from_value(event).map_err(|err| RouteError::new(&err))?;
+        let response = handler.handle(event).map_err(|err| RouteError::new(&err))?;
+        if crate::response::is_empty(&response) {
+            return Ok(MaybeEmpty(None));
+        }
+        serde_json::to_value(response)
+            .map(|response| MaybeEmpty(Some(response)))
+            .map_err(|err| RouteError::new(&err))
+    })
+}
+
+impl Handler<Value> for Router {
+    type Response = MaybeEmpty<Value>;
+    type Error = RouteError;
+
+    fn handle(&mut self, event: Value) -> Result<MaybeEmpty<Value>, RouteError> {
+        let source = EventSource::detect(&event);
+        match self.routes.iter_mut().find(|(s, _)| *s == source) {
+            Some((_, route)) => route(event),
+            None => Err(RouteError::unrouted(format!(
+                "no route for event source {source:?}"
+            ))),
+        }
+    }
+}
+
+impl Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field(
+                "routes",
+                &self.routes.iter().map(|(s, _)| s).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// An error returned by a [`Router`] route, a [`Registry`](crate::Registry) handler, or a
+/// [`Layer`](crate::Layer).
+#[derive(Debug, Clone)]
+pub struct RouteError {
+    type_name: String,
+    message: String,
+    recoverable: bool,
+}
+
+impl RouteError {
+    /// Creates an error with the type name and message of `err`.
+    pub fn new<E: ErrorTypeName + 'static>(err: &E) -> RouteError {
+        RouteError {
+            type_name: err.error_type_name().to_owned(),
+            message: crate::error::message(err),
+            recoverable: err.is_recoverable(),
+        }
+    }
+
+    pub(crate) fn unrouted(message: String) -> RouteError {
+        RouteError {
+            type_name: String::from(std::any::type_name::<RouteError>()),
+            message,
+            recoverable: true,
+        }
+    }
+
+    /// Returns the type name of the underlying error.
+    #[must_use]
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+impl ErrorTypeName for RouteError {
+    fn error_type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    fn is_recoverable(&self) -> bool {
+        self.recoverable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventSource, Router};
+    use crate::{Fatal, Handler, MaybeEmpty, NoResponse};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn detect() {
+        for (event, source) in [
+            (
+                json!({"Records": [{"eventSource": "aws:sqs"}]}),
+                EventSource::Sqs,
+            ),
+            (
+                json!({"Records": [{"eventSource": "aws:s3"}]}),
+                EventSource::Raw,
+            ),
+            (json!({"Records": []}), EventSource::Raw),
+            (json!({"httpMethod": "GET"}), EventSource::ApiGateway),
+            (
+                json!({"requestContext": {"http": {"method": "GET"}}}),
+                EventSource::ApiGateway,
+            ),
+            (
+                json!({"detail-type": "Scheduled Event", "source": "aws.events", "detail": {}}),
+                EventSource::EventBridge,
+            ),
+            (
+                json!({"source": "aws.events", "detail": {}}),
+                EventSource::Raw,
+            ),
+            (json!("event"), EventSource::Raw),
+        ] {
+            assert_eq!(EventSource::detect(&event), source, "{event}");
+        }
+    }
+
+    #[test]
+    fn routes() {
+        let mut router = Router::new()
+            .route(EventSource::Sqs, |batch: Value| {
+                Ok::<_, String>(batch["Records"].as_array().map_or(0, Vec::len))
+            })
+            .route(EventSource::Sqs, |_: Value| Ok::<_, String>(0))
+            .route(EventSource::EventBridge, |_: Value| {
+                Ok::<_, String>(NoResponse)
+            })
+            .route(EventSource::ApiGateway, |event: Value| {
+                Err::<(), _>(Fatal::new(format!("bad request: {}", event["httpMethod"])))
+            });
+        let sqs = json!({"Records": [{"eventSource": "aws:sqs"}, {"eventSource": "aws:sqs"}]});
+        assert_eq!(router.handle(sqs).unwrap(), MaybeEmpty(Some(json!(2))));
+        let event = json!({"detail-type": "Scheduled Event", "source": "aws.events", "detail": {}});
+        assert_eq!(router.handle(event).unwrap(), MaybeEmpty(None));
+
+        let err = router.handle(json!({"httpMethod": "GET"})).unwrap_err();
+        assert_eq!(err.to_string(), "bad request: \"GET\"");
+        assert_eq!(err.type_name(), "alloc::string::String");
+        assert!(!crate::ErrorTypeName::is_recoverable(&err));
+
+        let err = router.handle(json!({})).unwrap_err();
+        assert_eq!(err.to_string(), "no route for event source Raw");
+        assert_eq!(err.type_name(), "minlambda::router::RouteError");
+        assert!(crate::ErrorTypeName::is_recoverable(&err));
+    }
+}
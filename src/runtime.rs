@@ -0,0 +1,1302 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::background;
+use crate::client::{self, addrs_from_env};
+use crate::de;
+use crate::error::{self, Error, ErrorReport, Result};
+use crate::http::{self, Endpoint};
+#[cfg(feature = "reporting")]
+use crate::reporting::{self, Event, EventKind, Reporter};
+use crate::router::{self, RouteError};
+use crate::snapstart;
+use crate::transport::{Addr, TcpOptions, Transport};
+use crate::watchdog::{self, Watchdog};
+use crate::{context, Body, Client, Context, ErrorTypeName, Handler, MaybeEmpty};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::cell::{Cell, Ref, RefCell};
+use std::fmt::{self, Debug, Display};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Hook = Box<dyn Fn(&Context)>;
+type ErrorFormatter = Box<dyn Fn(&dyn Display) -> String>;
+type Predicate = Box<dyn Fn(&Value) -> bool>;
+type ErrorHook = Box<dyn Fn(&ErrorReport<'_>, &Context, Option<&[u8]>)>;
+type ErrorBody = Box<dyn Fn(&ErrorReport<'_>, &Context) -> serde_json::Result<Value>>;
+
+/// A configured runtime, for when the defaults used by [`run`](crate::run) aren't right.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// fn main() -> std::io::Result<()> {
+///     minlambda::Runtime::builder()
+///         .write_timeout(Duration::from_secs(5))
+///         .retries(2)
+///         .build()?
+///         .run(|event: serde_json::Value| Ok::<_, std::convert::Infallible>(event))
+/// }
+/// ```
+pub struct Runtime {
+    client: Client,
+    format_error: Option<ErrorFormatter>,
+    before_invocation: Option<Hook>,
+    after_invocation: Option<Hook>,
+    watchdog: Option<Watchdog>,
+    stack_size: Option<usize>,
+    warmup: Option<Predicate>,
+    warmup_response: Value,
+    max_failures: Option<u32>,
+    failures: Cell<u32>,
+    error_body: Option<ErrorBody>,
+    error_metadata: bool,
+    function_version: Option<String>,
+    invocations: Cell<u64>,
+    report_panics: bool,
+    trace_id_env: bool,
+    /// Set when the handler returns an unrecoverable error.
+    fatal: Cell<bool>,
+    on_error: Option<ErrorHook>,
+    #[cfg(feature = "reporting")]
+    reporter: Option<Arc<dyn Reporter>>,
+    event_snippet: Option<usize>,
+    redact: Vec<String>,
+    deserialize_failure: RefCell<DeserializeFailure>,
+    /// The current invocation's event, if it was read into memory.
+    event: RefCell<Vec<u8>>,
+    event_read: Cell<bool>,
+}
+
+impl Runtime {
+    /// Returns a [`Builder`] for configuring a runtime.
+    #[must_use]
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Returns the client this runtime uses to talk to the runtime API.
+    #[must_use]
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Runs the handler, the same as [`run`](crate::run).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it fails to report an error to the runtime interface.
+    pub fn run<H, D>(self, handler: H) -> !
+    where
+        H: Handler<D>,
+        D: DeserializeOwned,
+    {
+        let mut handler = handler;
+        self.run_with_context(|_, event| handler.handle(event))
+    }
+
+    /// Runs the handler with the invocation's context, the same as
+    /// [`run_with_context`](crate::run_with_context).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it fails to report an error to the runtime interface.
+    pub fn run_with_context<F, D, S, E>(self, handler: F) -> !
+    where
+        F: FnMut(Context, D) -> std::result::Result<S, E>,
+        D: DeserializeOwned,
+        S: Serialize,
+        E: ErrorTypeName + 'static,
+    {
+        let mut handler = handler;
+        self.run_loop(|runtime, context, body| runtime.handle(context, body, &mut handler))
+    }
+
+    /// Runs the handler like [`run`](Runtime::run), but calls it on a new thread for each
+    /// invocation, with the stack size set by [`Builder::stack_size`] (or the standard library's
+    /// default).
+    ///
+    /// This is for handlers that need a bigger stack than the main thread has, such as deeply
+    /// recursive transforms. Note that a stack overflow on the handler thread still aborts the
+    /// process.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it fails to report an error to the runtime interface, or if the
+    /// handler panics.
+    pub fn run_threaded<H, D>(self, handler: H) -> !
+    where
+        H: Handler<D> + Send,
+        H::Response: Send,
+        H::Error: Send,
+        D: DeserializeOwned + Send,
+    {
+        let mut handler = handler;
+        self.run_loop(|runtime, context, body| {
+            let Some(event) = runtime.read_event::<D>(&context.request_id, body)? else {
+                return Ok(());
+            };
+            let mut thread = std::thread::Builder::new().name(String::from("minlambda-handler"));
+            if let Some(stack_size) = runtime.stack_size {
+                thread = thread.stack_size(stack_size);
+            }
+            let handler = &mut handler;
+            let thread_context = context.clone();
+            let result = std::thread::scope(|scope| {
+                let report_panics = runtime.report_panics;
+                let thread = thread.spawn_scoped(scope, move || {
+                    if report_panics {
+                        crate::panic::mark_handler_thread();
+                    }
+                    context::set_current(Some(thread_context));
+                    handler.handle(event)
+                })?;
+                Ok::<_, Error>(
+                    thread
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+                )
+            })?;
+            match result {
+                Ok(response) => runtime.respond(&context.request_id, &response),
+                Err(err) => runtime.report_error(&context.request_id, &err),
+            }
+        })
+    }
+
+    /// Reports an initialization error and exits.
+    pub(crate) fn init_failed(&self, ty: &str, message: &str) -> ! {
+        client::init_failed(self.client.endpoint(), ty, message)
+    }
+
+    pub(crate) fn run_loop<F>(&self, invoke: F) -> !
+    where
+        F: FnMut(&Runtime, Context, Body) -> Result<()>,
+    {
+        let mut invoke = invoke;
+        loop {
+            self.step(&mut invoke);
+        }
+    }
+
+    /// Handles one invocation, reporting any errors that aren't the handler's own.
+    ///
+    /// Errors after an invocation is received are reported as that invocation's error. Errors
+    /// receiving an invocation, or reporting an invocation's error, are reported as initialization
+    /// errors. Returns whether an invocation was received, whether or not it succeeded.
+    pub(crate) fn step<F>(&self, invoke: &mut F) -> bool
+    where
+        F: FnMut(&Runtime, Context, Body) -> Result<()>,
+    {
+        let invocations = self.invocations.get();
+        let result = self.invoke_next(invoke);
+        let received = self.invocations.get() != invocations;
+        if self.fatal.get() {
+            std::process::exit(1);
+        }
+        let Err(inner_err) = result else {
+            self.failures.set(0);
+            return received;
+        };
+        let report = http::post_error(
+            self.client.endpoint(),
+            "init/error",
+            "minlambda::Error",
+            &error::message(&inner_err),
+        );
+        let Some(max_failures) = self.max_failures else {
+            if let Err(init_err) = report {
+                panic!(
+                    "failed to report initialization error: {}\ncaused by: {}",
+                    init_err, inner_err
+                );
+            }
+            return received;
+        };
+        if let Err(init_err) = report {
+            eprintln!("failed to report initialization error: {init_err}\ncaused by: {inner_err}");
+        }
+        let failures = self.failures.get() + 1;
+        self.failures.set(failures);
+        if failures >= max_failures {
+            eprintln!("giving up after {failures} consecutive failures");
+            std::process::exit(1);
+        }
+        received
+    }
+
+    pub(crate) fn invoke_next<F>(&self, invoke: F) -> Result<()>
+    where
+        F: FnOnce(&Runtime, Context, Body) -> Result<()>,
+    {
+        snapstart::checkpoint(&self.client);
+        let (context, body) = self.client.next_invocation()?;
+        self.invocations.set(self.invocations.get() + 1);
+        self.event_read.set(false);
+        #[cfg(feature = "reporting")]
+        reporting::clear_breadcrumbs();
+        if self.trace_id_env {
+            match &context.trace_id {
+                Some(trace_id) => std::env::set_var("_X_AMZN_TRACE_ID", trace_id),
+                None => std::env::remove_var("_X_AMZN_TRACE_ID"),
+            }
+        }
+        if let Some(hook) = &self.before_invocation {
+            hook(&context);
+        }
+        let claim = watchdog::begin();
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.start(&context, claim);
+        }
+        let after = (self.after_invocation.as_ref()).map(|hook| (hook, context.clone()));
+        context::set_current(Some(context.clone()));
+        let request_id = context.request_id.clone();
+        let result = if self.report_panics {
+            // Panics are reported once they unwind out of the handler, so that panics the handler
+            // catches itself aren't.
+            std::panic::catch_unwind(AssertUnwindSafe(|| invoke(self, context, body)))
+                .unwrap_or_else(|panic| {
+                    crate::panic::report_unwound(self.client.endpoint(), &*panic);
+                    std::panic::resume_unwind(panic)
+                })
+        } else {
+            invoke(self, context, body)
+        };
+        let result = result.or_else(|err| self.report_failure(&request_id, &err));
+        background::finish();
+        context::set_current(None);
+        if let Some((hook, context)) = after {
+            hook(&context);
+        }
+        result
+    }
+
+    pub(crate) fn handle<F, D, S, E>(&self, context: Context, body: Body, handler: F) -> Result<()>
+    where
+        F: FnOnce(Context, D) -> std::result::Result<S, E>,
+        D: DeserializeOwned,
+        S: Serialize,
+        E: ErrorTypeName + 'static,
+    {
+        let request_id = context.request_id.clone();
+        let Some(event) = self.read_event(&request_id, body)? else {
+            return Ok(());
+        };
+        match handler(context, event) {
+            Ok(response) => self.respond(&request_id, &response),
+            Err(err) => self.report_error(&request_id, &err),
+        }
+    }
+
+    /// Deserializes an invocation's event, or responds to it and returns `None` if it's a warm-up
+    /// event.
+    fn read_event<D: DeserializeOwned>(
+        &self,
+        request_id: &str,
+        mut body: Body,
+    ) -> Result<Option<D>> {
+        // The event is only kept in memory if the error hook, error message, or fallback handler
+        // might want it.
+        let fallback = matches!(
+            *self.deserialize_failure.borrow(),
+            DeserializeFailure::Fallback(_)
+        );
+        let buffered = if self.on_error.is_some() || self.event_snippet.is_some() || fallback {
+            Some(self.read_body(&mut body)?)
+        } else {
+            None
+        };
+        let result = match &self.warmup {
+            None => parse(buffered.as_deref(), body),
+            Some(is_warmup) => {
+                let event: Value = parse(buffered.as_deref(), body)?;
+                if is_warmup(&event) {
+                    self.respond(request_id, &self.warmup_response)?;
+                    return Ok(None);
+                }
+                de::from_value(event).map_err(Error::from)
+            }
+        };
+        match result {
+            Ok(event) => Ok(Some(event)),
+            Err(err @ Error::Deserialize(_)) => self
+                .deserialize_failed(request_id, buffered.as_deref(), err)
+                .map(|()| None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Handles an event that failed to deserialize according to the [`DeserializeFailure`] policy,
+    /// returning the error if it should be reported.
+    fn deserialize_failed(&self, request_id: &str, event: Option<&[u8]>, err: Error) -> Result<()> {
+        match &mut *self.deserialize_failure.borrow_mut() {
+            DeserializeFailure::Report => Err(err),
+            DeserializeFailure::Exit => {
+                self.fatal.set(true);
+                Err(err)
+            }
+            DeserializeFailure::Fallback(handler) => {
+                // Events that aren't even JSON can't be passed to the fallback handler.
+                let Some(event) = event.and_then(|event| serde_json::from_slice(event).ok()) else {
+                    return Err(err);
+                };
+                match handler(event) {
+                    Ok(response) => self.respond(request_id, &response),
+                    Err(err) => self.report_error(request_id, &err),
+                }
+            }
+        }
+    }
+
+    /// Reports an error that isn't the handler's own (such as the event failing to deserialize, or
+    /// the response failing to send) as the invocation's error.
+    fn report_failure(&self, request_id: &str, err: &Error) -> Result<()> {
+        let mut message = error::message(err);
+        if let (Error::Deserialize(_), Some(max_len)) = (err, self.event_snippet) {
+            let event = self.event.borrow();
+            if self.event_read.get() {
+                if let Some(snippet) = de::snippet(&event, max_len, &self.redact) {
+                    message = error::truncate(format!("{message}; event: {snippet}"));
+                }
+            }
+        }
+        let report = ErrorReport::new("minlambda::Error", &message);
+        self.observe(&report);
+        #[cfg(feature = "reporting")]
+        self.send_event(&report, || error::chain(err));
+        if !watchdog::claim() {
+            return Ok(());
+        }
+        http::post_error(
+            self.client.endpoint(),
+            &format!("invocation/{request_id}/error"),
+            "minlambda::Error",
+            &message,
+        )
+    }
+
+    /// Reads the whole event body into memory, keeping it for the error hook.
+    pub(crate) fn read_body(&self, body: &mut Body) -> Result<Ref<'_, [u8]>> {
+        let mut event = self.event.borrow_mut();
+        event.clear();
+        body.read_to_end(&mut event)?;
+        drop(event);
+        self.event_read.set(true);
+        Ok(Ref::map(self.event.borrow(), Vec::as_slice))
+    }
+
+    /// Sends an error to the reporter, if any.
+    #[cfg(feature = "reporting")]
+    fn send_event(&self, report: &ErrorReport<'_>, chain: impl FnOnce() -> Vec<String>) {
+        if let Some(reporter) = &self.reporter {
+            reporter.report(&Event::new(EventKind::Error, report, chain()));
+        }
+    }
+
+    /// Calls the error hook, if any.
+    fn observe(&self, report: &ErrorReport<'_>) {
+        if let (Some(on_error), Some(context)) = (&self.on_error, context::context()) {
+            let event = self.event.borrow();
+            on_error(
+                report,
+                &context,
+                self.event_read.get().then_some(&event[..]),
+            );
+        }
+    }
+
+    pub(crate) fn respond<S: Serialize>(&self, request_id: &str, response: &S) -> Result<()> {
+        if !watchdog::claim() {
+            return Ok(());
+        }
+        self.client.respond(request_id, response)
+    }
+
+    pub(crate) fn report_error<E: ErrorTypeName + 'static>(
+        &self,
+        request_id: &str,
+        err: &E,
+    ) -> Result<()> {
+        self.check_recoverable(err);
+        let message = self.error_message(err);
+        let stack_trace = error::stack_trace(err);
+        let mut report = ErrorReport {
+            stack_trace: &stack_trace,
+            ..ErrorReport::new(err.error_type_name(), &message)
+        };
+        if self.error_metadata {
+            report.request_id = Some(request_id);
+            report.function_version = self.function_version.as_deref();
+            report.invocation_count = Some(self.invocations.get());
+        }
+        self.observe(&report);
+        #[cfg(feature = "reporting")]
+        self.send_event(&report, || error::chain(err));
+        if !watchdog::claim() {
+            return Ok(());
+        }
+        let path = format!("invocation/{request_id}/error");
+        match (&self.error_body, context::context()) {
+            (Some(error_body), Some(context)) => {
+                let body = error_body(&report, &context).map_err(Error::serialize)?;
+                http::post_error_body(self.client.endpoint(), &path, report.error_type, &body)
+            }
+            _ => http::post_error_body(self.client.endpoint(), &path, report.error_type, &report),
+        }
+    }
+
+    /// Notes whether the process should exit after the current invocation because of `err`.
+    pub(crate) fn check_recoverable<E: ErrorTypeName + 'static>(&self, err: &E) {
+        if !err.is_recoverable() {
+            self.fatal.set(true);
+        }
+    }
+
+    pub(crate) fn error_message<E: ErrorTypeName + 'static>(&self, err: &E) -> String {
+        let message = error::message(err);
+        match &self.format_error {
+            Some(format_error) => error::truncate(format_error(&message)),
+            None => message,
+        }
+    }
+}
+
+/// Deserializes an event from memory if it was read there, or from the body otherwise.
+fn parse<T: DeserializeOwned>(buffered: Option<&[u8]>, body: Body) -> Result<T> {
+    Ok(match buffered {
+        Some(event) => de::from_slice(event)?,
+        None => de::from_reader(body)?,
+    })
+}
+
+impl Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Runtime")
+            .field("client", &self.client)
+            .field("watchdog", &self.watchdog.is_some())
+            .field("stack_size", &self.stack_size)
+            .field("warmup", &self.warmup.is_some())
+            .field("warmup_response", &self.warmup_response)
+            .field("max_failures", &self.max_failures)
+            .field("error_body", &self.error_body.is_some())
+            .field("error_metadata", &self.error_metadata)
+            .field("function_version", &self.function_version)
+            .field("invocations", &self.invocations.get())
+            .field("report_panics", &self.report_panics)
+            .field("trace_id_env", &self.trace_id_env)
+            .field("fatal", &self.fatal.get())
+            .field("on_error", &self.on_error.is_some())
+            .field("event_snippet", &self.event_snippet)
+            .field("redact", &self.redact)
+            .field("deserialize_failure", &self.deserialize_failure)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What to do when an event fails to deserialize; see [`Builder::deserialize_failure`].
+#[derive(Default)]
+#[non_exhaustive]
+pub enum DeserializeFailure {
+    /// Report the failure as the invocation's error, and continue with the next invocation.
+    #[default]
+    Report,
+    /// Report the failure as the invocation's error, then exit the process so that Lambda replaces
+    /// the execution environment.
+    Exit,
+    /// Pass the event to a fallback handler as a [`serde_json::Value`], and send its response or
+    /// error instead. Events that aren't valid JSON are reported as errors.
+    ///
+    /// Create this with [`DeserializeFailure::fallback`].
+    Fallback(Box<dyn FnMut(Value) -> std::result::Result<MaybeEmpty<Value>, RouteError>>),
+}
+
+impl DeserializeFailure {
+    /// Creates a [`DeserializeFailure::Fallback`] policy with a handler for events that fail to
+    /// deserialize.
+    pub fn fallback<H: Handler<Value> + 'static>(handler: H) -> DeserializeFailure {
+        DeserializeFailure::Fallback(router::erase(handler))
+    }
+}
+
+impl Debug for DeserializeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeFailure::Report => f.write_str("Report"),
+            DeserializeFailure::Exit => f.write_str("Exit"),
+            DeserializeFailure::Fallback(_) => f.debug_tuple("Fallback").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Configures a [`Runtime`].
+#[derive(Default)]
+pub struct Builder {
+    endpoint: Option<Addr>,
+    connect_timeout: Option<Duration>,
+    connect_attempts: Option<u32>,
+    nodelay: Option<bool>,
+    tcp_keepalive: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    keep_alive: Option<bool>,
+    pipeline: Option<bool>,
+    buffered: Option<bool>,
+    emulator: Option<bool>,
+    user_agent: Option<String>,
+    status_retries: Option<u32>,
+    max_header_line: Option<usize>,
+    max_headers: Option<usize>,
+    max_event_size: Option<usize>,
+    read_buffer: Option<usize>,
+    write_buffer: Option<usize>,
+    chunk_size: Option<usize>,
+    retries: u32,
+    format_error: Option<ErrorFormatter>,
+    before_invocation: Option<Hook>,
+    after_invocation: Option<Hook>,
+    watchdog: Option<Duration>,
+    stack_size: Option<usize>,
+    warmup: Option<Predicate>,
+    warmup_response: Value,
+    max_failures: Option<u32>,
+    backtraces: bool,
+    error_body: Option<ErrorBody>,
+    error_metadata: bool,
+    report_panics: bool,
+    trace_id_env: Option<bool>,
+    on_error: Option<ErrorHook>,
+    #[cfg(feature = "reporting")]
+    reporter: Option<Arc<dyn Reporter>>,
+    event_snippet: Option<usize>,
+    redact: Vec<String>,
+    deserialize_failure: DeserializeFailure,
+}
+
+impl Builder {
+    /// Sets the address of the runtime API, instead of reading it from the
+    /// `AWS_LAMBDA_RUNTIME_API` environment variable.
+    #[must_use]
+    pub fn endpoint(mut self, addr: SocketAddr) -> Builder {
+        self.endpoint = Some(Addr::Tcp(addr));
+        self
+    }
+
+    /// Sets the path of a Unix domain socket the runtime API is listening on, such as a local
+    /// emulator's, instead of reading the address from the `AWS_LAMBDA_RUNTIME_API` environment
+    /// variable (which can also give a socket path as a `unix://` URL).
+    #[cfg(unix)]
+    #[must_use]
+    pub fn unix_endpoint<P: AsRef<Path>>(mut self, path: P) -> Builder {
+        self.endpoint = Some(Addr::unix(path));
+        self
+    }
+
+    /// Sets the vsock context ID and port the runtime API is listening on, such as in a
+    /// Firecracker microVM test rig, instead of reading the address from the
+    /// `AWS_LAMBDA_RUNTIME_API` environment variable (which can also give them as a
+    /// `vsock://CID:PORT` URL).
+    ///
+    /// This is available with the `vsock` feature, on Linux.
+    #[cfg(feature = "vsock")]
+    #[must_use]
+    pub fn vsock_endpoint(mut self, cid: u32, port: u32) -> Builder {
+        self.endpoint = Some(Addr::Vsock { cid, port });
+        self
+    }
+
+    /// Sets a [`Transport`] to connect to the runtime API with, instead of connecting over TCP to
+    /// the address in the `AWS_LAMBDA_RUNTIME_API` environment variable. See the
+    /// [`transport`](crate::transport) module.
+    #[must_use]
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Builder {
+        self.endpoint = Some(Addr::custom(transport));
+        self
+    }
+
+    /// Sets a timeout for each attempt to connect to the runtime API. By default, connecting
+    /// waits as long as the operating system does.
+    ///
+    /// A connection attempt that times out fails with an
+    /// [`Error::Transport`](crate::Error::Transport) whose kind is
+    /// [`TimedOut`](std::io::ErrorKind::TimedOut).
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Builder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many times to try connecting to the runtime API before a request fails. By
+    /// default, each request tries once.
+    #[must_use]
+    pub fn connect_attempts(mut self, attempts: u32) -> Builder {
+        self.connect_attempts = Some(attempts);
+        self
+    }
+
+    /// Sets whether to disable Nagle's algorithm (with `TCP_NODELAY`) on TCP connections to the
+    /// runtime API, so that small writes are sent right away rather than waiting for earlier ones
+    /// to be acknowledged. By default, the operating system's setting is used.
+    #[must_use]
+    pub fn nodelay(mut self, nodelay: bool) -> Builder {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enables TCP keepalive (`SO_KEEPALIVE`) on TCP connections to the runtime API, with probes
+    /// sent once a connection has been idle for `time`. This can notice a connection kept for
+    /// reuse that was dropped while the execution environment was frozen. By default, keepalive
+    /// isn't enabled.
+    ///
+    /// This is available with the `keepalive` feature.
+    #[cfg(feature = "keepalive")]
+    #[must_use]
+    pub fn tcp_keepalive(mut self, time: Duration) -> Builder {
+        self.tcp_keepalive = Some(time);
+        self
+    }
+
+    /// Sets a timeout for reads from the runtime API. By default, reads never time out.
+    ///
+    /// Note that this also applies to waiting for the next invocation. A read that times out
+    /// fails with an [`Error::Transport`](crate::Error::Transport) whose kind is
+    /// [`TimedOut`](std::io::ErrorKind::TimedOut).
+    #[must_use]
+    pub fn read_timeout(mut self, timeout: Duration) -> Builder {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets a timeout for writes to the runtime API. By default, writes never time out.
+    ///
+    /// A write that times out fails like a read that does.
+    #[must_use]
+    pub fn write_timeout(mut self, timeout: Duration) -> Builder {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether to keep connections to the runtime API open and reuse them for later requests,
+    /// rather than connecting for every request. By default, connections are reused unless the
+    /// runtime API closes them.
+    #[must_use]
+    pub fn keep_alive(mut self, keep_alive: bool) -> Builder {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Sets whether to request the next invocation right after sending each response or error,
+    /// on the same connection and before the runtime API has answered, saving a round trip per
+    /// invocation. By default, the next invocation is requested once the runtime is ready for it.
+    ///
+    /// Requesting the next invocation tells Lambda that the current one is over, and it may freeze
+    /// the execution environment at any point after that. So with pipelining, work done after the
+    /// response is sent — [`defer`](crate::defer)red and
+    /// [background](crate::spawn_background) work, and the
+    /// [`after_invocation`](Builder::after_invocation) hook — may not run until the next
+    /// invocation arrives. This has no effect if [`keep_alive`](Builder::keep_alive) is off.
+    #[must_use]
+    pub fn pipeline(mut self, pipeline: bool) -> Builder {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    /// Sets whether to serialize each response and error in full before sending it with a
+    /// `Content-Length` header, rather than sending it with chunked transfer encoding as it's
+    /// serialized. By default, responses are sent as they're serialized.
+    ///
+    /// Some runtime API emulators and proxies mishandle chunked requests. Buffering also means a
+    /// response that fails to serialize is reported as an error without a partial response having
+    /// been sent first. Streamed responses are always sent with chunked transfer encoding.
+    #[must_use]
+    pub fn buffered(mut self, buffered: bool) -> Builder {
+        self.buffered = Some(buffered);
+        self
+    }
+
+    /// Sets whether to talk to the runtime API in the most conservative way, for local emulators
+    /// such as the [Runtime Interface Emulator][rie] (used when testing with `docker run`) and
+    /// proxies in front of them. By default, this is off.
+    ///
+    /// This changes the defaults of [`keep_alive`](Builder::keep_alive) to off and
+    /// [`buffered`](Builder::buffered) to on, so each request is made on a new connection and
+    /// sent with a `Content-Length`. Either can still be set explicitly.
+    ///
+    /// [rie]: https://github.com/aws/aws-lambda-runtime-interface-emulator
+    #[must_use]
+    pub fn emulator(mut self, emulator: bool) -> Builder {
+        self.emulator = Some(emulator);
+        self
+    }
+
+    /// Sets the `User-Agent` header of requests to the runtime API. By default, it's
+    /// `minlambda/VERSION (rust)`.
+    #[must_use]
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Builder {
+        self.user_agent = Some(user_agent.into().replace(['\r', '\n'], " "));
+        self
+    }
+
+    /// Sets how many times to retry a request that the runtime API responds to with HTTP 429, 500,
+    /// or 503, which usually means it's briefly overloaded. Each retry waits for as long as the
+    /// `Retry-After` header says (up to 10 seconds), or otherwise for a short, randomized delay
+    /// that doubles with each retry. By default, these requests are retried 3 times.
+    ///
+    /// Invocation events and responses streamed with a [`ResponseWriter`](crate::ResponseWriter)
+    /// aren't retried.
+    #[must_use]
+    pub fn status_retries(mut self, retries: u32) -> Builder {
+        self.status_retries = Some(retries);
+        self
+    }
+
+    /// Sets the longest status or header line to accept in a response from the runtime API, in
+    /// bytes. A response with a longer line fails with an
+    /// [`Error::Protocol`](crate::Error::Protocol). The default is 8 KiB.
+    #[must_use]
+    pub fn max_header_line(mut self, max: usize) -> Builder {
+        self.max_header_line = Some(max);
+        self
+    }
+
+    /// Sets the most header bytes to accept in a response from the runtime API, in total, which
+    /// also limits how many headers there can be. A response with more fails like one with too
+    /// long a line. The default is 64 KiB.
+    #[must_use]
+    pub fn max_headers(mut self, max: usize) -> Builder {
+        self.max_headers = Some(max);
+        self
+    }
+
+    /// Sets the largest invocation event to accept, in bytes. Reading a larger event fails with an
+    /// [`Error::PayloadTooLarge`](crate::Error::PayloadTooLarge), which is reported as the
+    /// invocation's error. The default is 8 MiB, more than Lambda allows for an event.
+    #[must_use]
+    pub fn max_event_size(mut self, max: usize) -> Builder {
+        self.max_event_size = Some(max);
+        self
+    }
+
+    /// Sets the capacity of the buffer for reading from each connection to the runtime API, in
+    /// bytes. A bigger buffer means fewer reads of large events; a smaller one, less memory. The
+    /// default is 8 KiB.
+    #[must_use]
+    pub fn read_buffer_size(mut self, size: usize) -> Builder {
+        self.read_buffer = Some(size);
+        self
+    }
+
+    /// Sets the capacity of the buffer for writing to each connection to the runtime API, in
+    /// bytes. The default is 8 KiB.
+    #[must_use]
+    pub fn write_buffer_size(mut self, size: usize) -> Builder {
+        self.write_buffer = Some(size);
+        self
+    }
+
+    /// Sets the size of the chunks that responses and errors are sent in when they're sent with
+    /// chunked transfer encoding (see [`buffered`](Builder::buffered)), including responses
+    /// written to a [`ResponseWriter`](crate::ResponseWriter), in bytes. Data is held until
+    /// there's a chunk's worth of it, or the writer is flushed. The default is 8 KiB.
+    #[must_use]
+    pub fn chunk_size(mut self, size: usize) -> Builder {
+        self.chunk_size = Some(size);
+        self
+    }
+
+    /// Sets how many times to retry sending a response or error to the runtime API if the
+    /// connection fails, backing off exponentially between attempts. Responses saying that the
+    /// runtime API is overloaded or failed are retried up to
+    /// [`status_retries`](Builder::status_retries) times instead, and other errors aren't retried,
+    /// since they'd happen again. By default, requests are not retried.
+    #[must_use]
+    pub fn retries(mut self, retries: u32) -> Builder {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how handler errors are turned into the `errorMessage` reported to the runtime API. The
+    /// function is given the error's message joined with its source chain (see
+    /// [`ErrorTypeName`](crate::ErrorTypeName)); by default, that message is used as is.
+    #[must_use]
+    pub fn format_error<F>(mut self, f: F) -> Builder
+    where
+        F: Fn(&dyn Display) -> String + 'static,
+    {
+        self.format_error = Some(Box::new(f));
+        self
+    }
+
+    /// Sets a hook to call with each error before it's reported for an invocation, along with the
+    /// invocation's context and raw event, so errors can be sent to metrics or an error tracker
+    /// without wrapping every handler.
+    ///
+    /// The hook is called both for errors returned by the handler and for errors minlambda reports
+    /// on its own (such as events that fail to deserialize). The event is `None` for handlers that
+    /// stream the event body, such as those passed to [`run_reader`](crate::run_reader); setting
+    /// this hook makes [`run`](Runtime::run) and similar read each event into memory before
+    /// deserializing it.
+    #[must_use]
+    pub fn on_error<F>(mut self, f: F) -> Builder
+    where
+        F: Fn(&ErrorReport<'_>, &Context, Option<&[u8]>) + 'static,
+    {
+        self.on_error = Some(Box::new(f));
+        self
+    }
+
+    /// Sets the runtime to include up to `max_len` bytes of the event in the error message when an
+    /// event fails to deserialize, so the failure can be reproduced without logging every event.
+    /// See also [`redact`](Builder::redact).
+    ///
+    /// This makes [`run`](Runtime::run) and similar read each event into memory before
+    /// deserializing it. It has no effect for handlers that stream the event body, such as those
+    /// passed to [`run_writer`](crate::run_writer).
+    #[must_use]
+    pub fn event_snippet(mut self, max_len: usize) -> Builder {
+        self.event_snippet = Some(max_len);
+        self
+    }
+
+    /// Sets the names of object fields whose values are replaced with `"[redacted]"` in the event
+    /// included by [`event_snippet`](Builder::event_snippet), wherever they appear in the event.
+    /// If any fields are set, events that aren't valid JSON are not included.
+    #[must_use]
+    pub fn redact<I>(mut self, fields: I) -> Builder
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.redact.extend(fields.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets what happens when an event fails to deserialize as the handler's event type. By
+    /// default, the failure is reported as the invocation's error and the runtime moves on to the
+    /// next invocation.
+    ///
+    /// This applies to [`run`](Runtime::run) and similar, where minlambda deserializes the event.
+    #[must_use]
+    pub fn deserialize_failure(mut self, policy: DeserializeFailure) -> Builder {
+        self.deserialize_failure = policy;
+        self
+    }
+
+    /// Sets a hook to call with the context of each invocation, before the handler is called.
+    #[must_use]
+    pub fn before_invocation<F>(mut self, f: F) -> Builder
+    where
+        F: Fn(&Context) + 'static,
+    {
+        self.before_invocation = Some(Box::new(f));
+        self
+    }
+
+    /// Sets a hook to call with the context of each invocation, after the response or error has
+    /// been sent.
+    #[must_use]
+    pub fn after_invocation<F>(mut self, f: F) -> Builder
+    where
+        F: Fn(&Context) + 'static,
+    {
+        self.after_invocation = Some(Box::new(f));
+        self
+    }
+
+    /// Starts a watchdog thread that reports a `TaskTimedOut` error for any invocation whose
+    /// handler is still running `margin` before the invocation's deadline.
+    ///
+    /// Without a watchdog, Lambda stops a handler that runs past its deadline and reports a
+    /// generic timeout. With one, the error is reported while the handler is still running, so it
+    /// shows up as the invocation's error; whatever the handler eventually returns is discarded.
+    #[must_use]
+    pub fn watchdog(mut self, margin: Duration) -> Builder {
+        self.watchdog = Some(margin);
+        self
+    }
+
+    /// Sets the stack size, in bytes, of the thread each invocation's handler is called on by
+    /// [`Runtime::run_threaded`].
+    #[must_use]
+    pub fn stack_size(mut self, stack_size: usize) -> Builder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets a predicate for detecting warm-up events (the pings sent by keep-warm plugins) so they
+    /// can be responded to without calling the handler.
+    ///
+    /// [`is_serverless_warmup`](crate::is_serverless_warmup) detects the events sent by
+    /// `serverless-plugin-warmup`. Warm-up events get an empty response unless
+    /// [`warmup_response`](Builder::warmup_response) is set.
+    ///
+    /// Warm-up detection applies to [`Runtime::run`], [`Runtime::run_with_context`], and
+    /// [`Runtime::run_threaded`].
+    #[must_use]
+    pub fn warmup<F>(mut self, predicate: F) -> Builder
+    where
+        F: Fn(&Value) -> bool + 'static,
+    {
+        self.warmup = Some(Box::new(predicate));
+        self
+    }
+
+    /// Sets the response sent for warm-up events detected by the [`warmup`](Builder::warmup)
+    /// predicate.
+    #[must_use]
+    pub fn warmup_response(mut self, response: Value) -> Builder {
+        self.warmup_response = response;
+        self
+    }
+
+    /// Sets how many invocations in a row can fail to be received or reported before the process
+    /// exits with a nonzero status, so that Lambda replaces the execution environment instead of
+    /// the runtime spinning against a broken runtime API. Errors returned by the handler don't
+    /// count.
+    ///
+    /// By default the runtime never gives up on its own, but panics if it cannot report one of
+    /// these failures to the runtime API; with this set, such failures are printed and counted
+    /// instead.
+    #[must_use]
+    pub fn max_failures(mut self, max_failures: u32) -> Builder {
+        self.max_failures = Some(max_failures);
+        self
+    }
+
+    /// Sets whether [`Traced`](crate::Traced) errors always capture a backtrace, which is sent as
+    /// the `stackTrace` of the error. By default, backtraces are captured only if the
+    /// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables enable them.
+    ///
+    /// This applies to the whole process once the runtime is built.
+    #[must_use]
+    pub fn backtraces(mut self, backtraces: bool) -> Builder {
+        self.backtraces = backtraces;
+        self
+    }
+
+    /// Sets a function that builds the body of handler error reports, for when the errors should
+    /// follow some other schema (such as including error codes or correlation IDs). By default,
+    /// the [`ErrorReport`] itself is sent.
+    ///
+    /// ```rust,no_run
+    /// fn main() -> std::io::Result<()> {
+    ///     minlambda::Runtime::builder()
+    ///         .error_body(|report, context| {
+    ///             serde_json::json!({
+    ///                 "errorType": report.error_type,
+    ///                 "errorMessage": report.error_message,
+    ///                 "requestId": context.request_id,
+    ///             })
+    ///         })
+    ///         .build()?
+    ///         .run(|name: String| Ok::<_, String>(format!("Hello, {name}!")))
+    /// }
+    /// ```
+    ///
+    /// This is only used for errors returned by the handler. Errors minlambda reports on its own
+    /// (such as events that fail to deserialize) are always sent as an `ErrorReport`.
+    #[must_use]
+    pub fn error_body<F, T>(mut self, f: F) -> Builder
+    where
+        F: Fn(&ErrorReport<'_>, &Context) -> T + 'static,
+        T: Serialize,
+    {
+        self.error_body = Some(Box::new(move |report, context| {
+            serde_json::to_value(f(report, context))
+        }));
+        self
+    }
+
+    /// Sets whether to include the request ID, function version, and a count of invocations
+    /// received by this execution environment in handler error reports (see [`ErrorReport`]), so
+    /// that errors can be correlated without searching the function's logs. By default, they are
+    /// not included.
+    #[must_use]
+    pub fn error_metadata(mut self, error_metadata: bool) -> Builder {
+        self.error_metadata = error_metadata;
+        self
+    }
+
+    /// Sets whether to report panics in the handler to the runtime API: as the current
+    /// invocation's error if one is being handled, or as an initialization error otherwise. This
+    /// installs a panic hook; the previous panic hook still runs first.
+    ///
+    /// Without this, a panic ends the process without reporting anything, and Lambda reports only
+    /// that the runtime exited. Only panics on the thread that builds the runtime (which is the one
+    /// that runs it) and the threads [`Runtime::run_threaded`] calls the handler on are reported;
+    /// panics on other threads are left alone.
+    ///
+    /// For binaries built with `panic = "abort"`, panics are reported from the hook, right before
+    /// the process aborts. Otherwise, a panic during an invocation is reported once it unwinds out
+    /// of the handler, so a panic the handler catches (with
+    /// [`catch_unwind`](std::panic::catch_unwind)) isn't reported and doesn't stop the invocation's
+    /// result from being sent. Panics outside of an invocation are reported as soon as they
+    /// happen, even if they're caught. Either way, if the invocation's result (or the watchdog's
+    /// timeout error) has already started being sent, the panic isn't reported.
+    #[must_use]
+    pub fn report_panics(mut self, report_panics: bool) -> Builder {
+        self.report_panics = report_panics;
+        self
+    }
+
+    /// Sets whether to set the `_X_AMZN_TRACE_ID` environment variable to each invocation's AWS
+    /// X-Ray tracing header, for code that only looks for it there, and remove it for invocations
+    /// without one. By default, it's only available as [`Context::trace_id`], to pass along to AWS
+    /// SDKs and the like.
+    ///
+    /// Changing the environment while another thread reads it is undefined behavior on some
+    /// platforms, so turning this on is a promise that no other threads are running when an
+    /// invocation arrives. Building the runtime fails if the [`watchdog`](Builder::watchdog),
+    /// which runs on a thread of its own, is also on.
+    #[must_use]
+    pub fn trace_id_env(mut self, trace_id_env: bool) -> Builder {
+        self.trace_id_env = Some(trace_id_env);
+        self
+    }
+
+    /// Sets a [`Reporter`] to send errors and panics to, such as an error tracker. See the
+    /// [`reporting`](crate::reporting) module.
+    ///
+    /// This installs a panic hook when the runtime is built, and is available with the `reporting`
+    /// feature.
+    #[cfg(feature = "reporting")]
+    #[must_use]
+    pub fn reporter<R: Reporter + 'static>(mut self, reporter: R) -> Builder {
+        self.reporter = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Builds the runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no endpoint was set and the `AWS_LAMBDA_RUNTIME_API` environment
+    /// variable is missing or cannot be resolved, or if both
+    /// [`trace_id_env`](Builder::trace_id_env) and the [`watchdog`](Builder::watchdog) are on.
+    pub fn build(self) -> Result<Runtime> {
+        let trace_id_env = self.trace_id_env.unwrap_or(false);
+        if trace_id_env && self.watchdog.is_some() {
+            return Err(Error::Config(String::from(
+                "`trace_id_env` can't be used with the watchdog, whose thread would be running \
+                 when `_X_AMZN_TRACE_ID` is set for each invocation",
+            )));
+        }
+        let addrs = match self.endpoint {
+            Some(addr) => vec![addr],
+            None => addrs_from_env()?,
+        };
+        let emulator = self.emulator.unwrap_or(false);
+        let mut endpoint = Endpoint {
+            addr: addrs[0].clone(),
+            connect_timeout: self.connect_timeout,
+            tcp: TcpOptions {
+                nodelay: self.nodelay.unwrap_or(false),
+                #[cfg(feature = "keepalive")]
+                keepalive: self.tcp_keepalive,
+            },
+            connect_attempts: self.connect_attempts.unwrap_or(1).max(1),
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            keep_alive: self.keep_alive.unwrap_or(!emulator),
+            pipeline: self.pipeline.unwrap_or(false),
+            buffered: self.buffered.unwrap_or(emulator),
+            user_agent: Arc::from(self.user_agent.as_deref().unwrap_or(http::USER_AGENT)),
+            retries: self.retries,
+            status_retries: self.status_retries.unwrap_or(http::STATUS_RETRIES),
+            max_header_line: self.max_header_line.unwrap_or(http::MAX_HEADER_LINE),
+            max_headers: self.max_headers.unwrap_or(http::MAX_HEADERS),
+            max_event: self.max_event_size.unwrap_or(http::MAX_EVENT),
+            read_buffer: self.read_buffer.unwrap_or(http::BUFFER_SIZE).max(1),
+            write_buffer: self.write_buffer.unwrap_or(http::BUFFER_SIZE).max(1),
+            chunk_size: self.chunk_size.unwrap_or(http::BUFFER_SIZE).max(1),
+        };
+        endpoint.choose_addr(&addrs);
+        client::set_runtime_endpoint(endpoint.clone());
+        // The reporter's hook is installed first so that it runs before panics are reported.
+        #[cfg(feature = "reporting")]
+        if let Some(reporter) = &self.reporter {
+            reporting::install(Arc::clone(reporter));
+        }
+        if self.report_panics {
+            crate::panic::install(endpoint.clone());
+        }
+        if self.backtraces {
+            error::force_backtraces();
+        }
+        Ok(Runtime {
+            client: Client::with_endpoint(endpoint.clone()),
+            format_error: self.format_error,
+            before_invocation: self.before_invocation,
+            after_invocation: self.after_invocation,
+            watchdog: (self.watchdog).map(|margin| Watchdog::spawn(endpoint, margin)),
+            stack_size: self.stack_size,
+            warmup: self.warmup,
+            warmup_response: self.warmup_response,
+            max_failures: self.max_failures,
+            failures: Cell::new(0),
+            error_body: self.error_body,
+            error_metadata: self.error_metadata,
+            function_version: std::env::var("AWS_LAMBDA_FUNCTION_VERSION").ok(),
+            invocations: Cell::new(0),
+            report_panics: self.report_panics,
+            trace_id_env,
+            fatal: Cell::new(false),
+            on_error: self.on_error,
+            #[cfg(feature = "reporting")]
+            reporter: self.reporter,
+            event_snippet: self.event_snippet,
+            redact: self.redact,
+            deserialize_failure: RefCell::new(self.deserialize_failure),
+            event: RefCell::new(Vec::new()),
+            event_read: Cell::new(false),
+        })
+    }
+}
+
+impl Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("endpoint", &self.endpoint)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("connect_attempts", &self.connect_attempts)
+            .field("nodelay", &self.nodelay)
+            .field("tcp_keepalive", &self.tcp_keepalive)
+            .field("read_timeout", &self.read_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .field("keep_alive", &self.keep_alive)
+            .field("pipeline", &self.pipeline)
+            .field("buffered", &self.buffered)
+            .field("emulator", &self.emulator)
+            .field("user_agent", &self.user_agent)
+            .field("status_retries", &self.status_retries)
+            .field("max_header_line", &self.max_header_line)
+            .field("max_headers", &self.max_headers)
+            .field("max_event_size", &self.max_event_size)
+            .field("read_buffer", &self.read_buffer)
+            .field("write_buffer", &self.write_buffer)
+            .field("chunk_size", &self.chunk_size)
+            .field("retries", &self.retries)
+            .field("watchdog", &self.watchdog)
+            .field("stack_size", &self.stack_size)
+            .field("warmup", &self.warmup.is_some())
+            .field("warmup_response", &self.warmup_response)
+            .field("max_failures", &self.max_failures)
+            .field("backtraces", &self.backtraces)
+            .field("error_body", &self.error_body.is_some())
+            .field("error_metadata", &self.error_metadata)
+            .field("report_panics", &self.report_panics)
+            .field("trace_id_env", &self.trace_id_env)
+            .field("on_error", &self.on_error.is_some())
+            .field("event_snippet", &self.event_snippet)
+            .field("redact", &self.redact)
+            .field("deserialize_failure", &self.deserialize_failure)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeserializeFailure, Runtime};
+    use crate::watchdog::TEST_LOCK;
+    use serde_json::Value;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::PoisonError;
+    use std::thread::JoinHandle;
+
+    /// Serves one invocation of `event` on a new runtime with `policy`, returning whether the
+    /// runtime should exit, and the request line and body of the request it made for the
+    /// invocation's result.
+    fn invoke(policy: DeserializeFailure, event: &'static str) -> (bool, String, String) {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let runtime = Runtime::builder()
+            .endpoint(listener.local_addr().unwrap())
+            .keep_alive(false)
+            .buffered(true)
+            .deserialize_failure(policy)
+            .build()
+            .unwrap();
+        let server: JoinHandle<(String, String)> = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [
+                format!(
+                    "HTTP/1.1 200 OK\r\nLambda-Runtime-Aws-Request-Id: r1\r\n\
+                     Content-Length: {}\r\n\r\n{event}",
+                    event.len()
+                ),
+                String::from("HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n"),
+            ] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                (&stream).write_all(response.as_bytes()).unwrap();
+                requests.push((request_line.trim_end().to_owned(), body));
+            }
+            let (request_line, body) = requests.pop().unwrap();
+            (request_line, String::from_utf8(body).unwrap())
+        });
+        runtime
+            .invoke_next(|runtime, context, body| {
+                runtime.handle(context, body, |_, event: u32| Ok::<_, String>(event))
+            })
+            .unwrap();
+        let (request_line, body) = server.join().unwrap();
+        (runtime.fatal.get(), request_line, body)
+    }
+
+    #[test]
+    fn deserialize_failure() {
+        let (fatal, request_line, body) = invoke(DeserializeFailure::Report, r#""one""#);
+        assert!(!fatal);
+        assert_eq!(
+            request_line,
+            "POST /2018-06-01/runtime/invocation/r1/error HTTP/1.1"
+        );
+        assert!(body.contains("expected u32"), "{}", body);
+
+        let (fatal, request_line, _) = invoke(DeserializeFailure::Exit, r#""one""#);
+        assert!(fatal);
+        assert_eq!(
+            request_line,
+            "POST /2018-06-01/runtime/invocation/r1/error HTTP/1.1"
+        );
+
+        let fallback = DeserializeFailure::fallback(|event: Value| {
+            Ok::<_, String>(format!("fell back for {event}"))
+        });
+        let (fatal, request_line, body) = invoke(fallback, r#""one""#);
+        assert!(!fatal);
+        assert_eq!(
+            request_line,
+            "POST /2018-06-01/runtime/invocation/r1/response HTTP/1.1"
+        );
+        assert_eq!(body, r#""fell back for \"one\"""#);
+
+        let (_, request_line, body) = invoke(DeserializeFailure::Exit, "1");
+        assert_eq!(
+            request_line,
+            "POST /2018-06-01/runtime/invocation/r1/response HTTP/1.1"
+        );
+        assert_eq!(body, "1");
+    }
+}
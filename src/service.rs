@@ -0,0 +1,77 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::ErrorTypeName;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// How long the handler gets to finish any in-progress invocation and shut down after `SIGTERM`.
+/// Lambda gives the process about half a second.
+const SHUTDOWN_BUDGET: Duration = Duration::from_millis(300);
+
+/// A stateful handler with an initialization and shutdown lifecycle, for use with
+/// [`run_service`].
+pub trait ServiceHandler: Sized + Send + 'static {
+    /// The event type, deserialized from JSON.
+    type Event: DeserializeOwned;
+    /// The response type, sent back to the runtime API as JSON.
+    type Response: Serialize;
+    /// The error type, reported to the runtime API if handling an event fails.
+    type Error: ErrorTypeName + 'static;
+    /// The error type, reported to the runtime API if initialization fails.
+    type InitError: ErrorTypeName + 'static;
+
+    /// Constructs the handler. This is called once, before any invocations are handled.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported to the runtime API as an initialization error, after which the process
+    /// exits.
+    fn init() -> Result<Self, Self::InitError>;
+
+    /// Handles an invocation event.
+    ///
+    /// # Errors
+    ///
+    /// Errors are reported to the runtime API as the invocation's error.
+    fn handle(&mut self, event: Self::Event) -> Result<Self::Response, Self::Error>;
+
+    /// Cleans up before the execution environment shuts down. This is called when the process
+    /// receives `SIGTERM`, after any in-progress invocation completes. If the invocation and
+    /// shutdown together take longer than 300 ms, the process exits without waiting for them.
+    ///
+    /// The default implementation does nothing.
+    fn shutdown(&mut self) {}
+}
+
+/// [`run`](crate::run), for [`ServiceHandler`]s.
+///
+/// The handler is constructed with [`ServiceHandler::init`] before the first invocation, and
+/// [`ServiceHandler::shutdown`] is called when the process receives `SIGTERM` (which Lambda sends
+/// before shutting down the execution environment if any extensions are registered).
+///
+/// This function is otherwise the same as `run`: it does not return.
+///
+/// # Panics
+///
+/// In addition to the panics described in `run`, this function panics if it cannot report an
+/// initialization error or register a `SIGTERM` handler.
+pub fn run_service<S: ServiceHandler>() -> ! {
+    let runtime = crate::default_runtime();
+    let service = match S::init() {
+        Ok(service) => service,
+        Err(err) => runtime.init_failed(err.error_type_name(), &crate::error::message(&err)),
+    };
+    let service = Arc::new(Mutex::new(service));
+
+    // The handler is locked while it handles an invocation, so this waits for one in progress.
+    let shutdown = Arc::clone(&service);
+    crate::on_shutdown(SHUTDOWN_BUDGET, move || lock(&shutdown).shutdown());
+
+    runtime.run(move |event: S::Event| lock(&service).handle(event))
+}
+
+fn lock<S>(service: &Mutex<S>) -> MutexGuard<'_, S> {
+    service.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,62 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use signal_hook::{consts::SIGTERM, iterator::Signals};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Mutex, Once, PoisonError};
+use std::time::Duration;
+
+type Hook = (Duration, Box<dyn FnOnce() + Send>);
+
+static HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+static LISTEN: Once = Once::new();
+
+/// Registers a function to call when the process receives `SIGTERM`, for flushing buffers,
+/// closing connections, or persisting state before the execution environment shuts down.
+///
+/// Lambda sends `SIGTERM` (if any extensions are registered) and then gives the runtime around
+/// half a second before killing it. Each function gets at most `budget` to run, after which it's
+/// left running and the next one is called; once they've all had their turn, the process exits.
+/// They're called in the order they were registered, on a thread of their own, while any
+/// in-progress invocation carries on.
+///
+/// This is available with the `signal` feature.
+///
+/// # Panics
+///
+/// Panics if a `SIGTERM` handler can't be registered.
+pub fn on_shutdown<F>(budget: Duration, f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    HOOKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push((budget, Box::new(f)));
+    LISTEN.call_once(|| {
+        let mut signals = Signals::new([SIGTERM]).expect("could not register SIGTERM handler");
+        std::thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                shut_down();
+                std::process::exit(0);
+            }
+        });
+    });
+}
+
+/// Calls the registered functions, each for no longer than its budget.
+fn shut_down() {
+    let hooks = std::mem::take(&mut *HOOKS.lock().unwrap_or_else(PoisonError::into_inner));
+    for (budget, hook) in hooks {
+        let (done, finished) = mpsc::channel();
+        std::thread::spawn(move || {
+            hook();
+            let _ = done.send(());
+        });
+        // A hook that panicked has already had its panic printed.
+        if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(budget) {
+            eprintln!("shutdown hook did not finish within {budget:?}");
+        }
+    }
+}
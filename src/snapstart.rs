@@ -0,0 +1,217 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Hooks for [Lambda SnapStart][snapstart], which snapshots an execution environment once it has
+//! initialized and starts new ones from the snapshot.
+//!
+//! Whatever was set up during initialization is restored along with the snapshot, including
+//! state that doesn't survive being copied or frozen for a while: connections that have since
+//! been closed, credentials that have expired, and anything that's meant to be unique. Functions
+//! registered with [`before_checkpoint`] run before the snapshot is taken, to close or drop such
+//! things, and functions registered with [`after_restore`] run in each restored execution
+//! environment, to set them up again.
+//!
+//! Random number generators are the easiest of these to miss: every execution environment restored
+//! from the same snapshot starts with the same generator state, and so generates the same
+//! "random" numbers, UUIDs, and nonces as the others. Generators registered with [`reseed`] get a
+//! fresh seed from the operating system before any [`after_restore`] function is called, and
+//! [`unique_id`] is regenerated too.
+//!
+//! ```rust,no_run
+//! use minlambda::snapstart;
+//!
+//! fn main() -> ! {
+//!     snapstart::before_checkpoint(|| {
+//!         eprintln!("closing connections");
+//!         Ok::<_, std::convert::Infallible>(())
+//!     });
+//!     snapstart::after_restore(|| {
+//!         eprintln!("reconnecting");
+//!         Ok::<_, std::convert::Infallible>(())
+//!     });
+//!     minlambda::run_ok(|_: serde::de::IgnoredAny| "Hello, world!")
+//! }
+//! ```
+//!
+//! The `run` functions take the snapshot (by asking the runtime API for
+//! [`restore/next`](crate::Client::restore_next)) and call the hooks just before asking for the
+//! first invocation, if the execution environment is being initialized for a snapshot. Otherwise,
+//! the hooks are never called.
+//!
+//! [snapstart]: https://docs.aws.amazon.com/lambda/latest/dg/snapstart.html
+
+use crate::error::message;
+use crate::{Client, ErrorTypeName};
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::Read;
+use std::sync::{Mutex, Once, PoisonError};
+use std::time::SystemTime;
+
+/// The error type and message of a hook that failed.
+type Failure = (String, String);
+
+type Hook = Box<dyn FnOnce() -> Result<(), Failure> + Send>;
+
+static BEFORE_CHECKPOINT: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+static AFTER_RESTORE: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+type Reseed = Box<dyn FnOnce([u8; 32]) + Send>;
+
+static RESEED: Mutex<Vec<Reseed>> = Mutex::new(Vec::new());
+
+static UNIQUE_ID: Mutex<Option<u128>> = Mutex::new(None);
+
+static CHECKPOINT: Once = Once::new();
+
+/// Registers a function to call before the snapshot is taken.
+///
+/// These functions are called in the reverse of the order they were registered, so that
+/// something registered after what it depends on is taken down first. If one fails, the error is
+/// reported as an initialization error and the process exits.
+pub fn before_checkpoint<F, E>(f: F)
+where
+    F: FnOnce() -> Result<(), E> + Send + 'static,
+    E: ErrorTypeName + 'static,
+{
+    push(&BEFORE_CHECKPOINT, f);
+}
+
+/// Registers a function to call after the execution environment is restored from the snapshot.
+///
+/// These functions are called in the order they were registered. If one fails, the error is
+/// reported to the runtime API as a restore error and the process exits.
+pub fn after_restore<F, E>(f: F)
+where
+    F: FnOnce() -> Result<(), E> + Send + 'static,
+    E: ErrorTypeName + 'static,
+{
+    push(&AFTER_RESTORE, f);
+}
+
+/// Registers a function to re-seed a random number generator (or anything else that's meant to
+/// differ between execution environments) after the execution environment is restored from the
+/// snapshot.
+///
+/// The function is called with 32 bytes read from the operating system's random number generator,
+/// different for each function. These functions are called in the order they were registered,
+/// before any registered with [`after_restore`], so those can rely on them.
+///
+/// ```rust
+/// use std::sync::{Arc, Mutex};
+///
+/// # struct Rng([u8; 32]);
+/// # impl Rng { fn from_seed(seed: [u8; 32]) -> Rng { Rng(seed) } }
+/// let rng = Arc::new(Mutex::new(Rng::from_seed([0; 32])));
+/// let reseeded = Arc::clone(&rng);
+/// minlambda::snapstart::reseed(move |seed| {
+///     *reseeded.lock().unwrap() = Rng::from_seed(seed);
+/// });
+/// ```
+pub fn reseed<F>(f: F)
+where
+    F: FnOnce([u8; 32]) + Send + 'static,
+{
+    (RESEED.lock().unwrap_or_else(PoisonError::into_inner)).push(Box::new(f));
+}
+
+/// Returns a random identifier for this execution environment, for deriving things that have to
+/// be unique to it (such as a UUID namespace or a node ID) from.
+///
+/// It's generated the first time it's asked for and regenerated after the execution environment
+/// is restored from the snapshot, so it isn't shared with any other environment restored from the
+/// same one. Anything derived from it before the snapshot has to be derived again, with
+/// [`after_restore`].
+#[must_use]
+pub fn unique_id() -> u128 {
+    let mut id = UNIQUE_ID.lock().unwrap_or_else(PoisonError::into_inner);
+    *id.get_or_insert_with(|| {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&entropy()[..16]);
+        u128::from_le_bytes(bytes)
+    })
+}
+
+/// Returns 32 bytes from `/dev/urandom`.
+///
+/// Outside of Lambda, where there might not be a `/dev/urandom`, this falls back to hashing the
+/// time and process ID with the standard library's randomly-keyed hasher. That's no use for
+/// cryptography, but it's still different for each call and each process.
+fn entropy() -> [u8; 32] {
+    let mut seed = [0; 32];
+    let read = File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut seed));
+    if read.is_err() {
+        // `RandomState::new` only takes new random keys once per thread, so the time is what
+        // keeps a restored copy of this process from repeating the original.
+        for (i, chunk) in seed.chunks_mut(8).enumerate() {
+            let hash = RandomState::new().hash_one((i, SystemTime::now(), std::process::id()));
+            chunk.copy_from_slice(&hash.to_le_bytes());
+        }
+    }
+    seed
+}
+
+fn push<F, E>(hooks: &Mutex<Vec<Hook>>, f: F)
+where
+    F: FnOnce() -> Result<(), E> + Send + 'static,
+    E: ErrorTypeName + 'static,
+{
+    let hook = move || f().map_err(|err| (String::from(err.error_type_name()), message(&err)));
+    (hooks.lock().unwrap_or_else(PoisonError::into_inner)).push(Box::new(hook));
+}
+
+/// Returns whether the execution environment is being initialized for a snapshot.
+fn enabled() -> bool {
+    std::env::var_os("AWS_LAMBDA_INITIALIZATION_TYPE").is_some_and(|ty| ty == "snap-start")
+}
+
+/// Takes the snapshot, calling the hooks around it, if the execution environment is being
+/// initialized for one and it hasn't been taken yet.
+///
+/// # Panics
+///
+/// Panics if it can't wait for the restore, or report a hook's error.
+pub(crate) fn checkpoint(client: &Client) {
+    CHECKPOINT.call_once(|| {
+        if enabled() {
+            checkpoint_now(client);
+        }
+    });
+}
+
+fn checkpoint_now(client: &Client) {
+    for hook in take(&BEFORE_CHECKPOINT).into_iter().rev() {
+        if let Err((ty, message)) = hook() {
+            report(client, "init/error", &ty, &message);
+        }
+    }
+    client
+        .restore_next()
+        .unwrap_or_else(|err| panic!("failed to wait for restore: {}", err));
+    *UNIQUE_ID.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    for reseed in std::mem::take(&mut *RESEED.lock().unwrap_or_else(PoisonError::into_inner)) {
+        reseed(entropy());
+    }
+    for hook in take(&AFTER_RESTORE) {
+        if let Err((ty, message)) = hook() {
+            report(client, "restore/error", &ty, &message);
+        }
+    }
+}
+
+fn take(hooks: &Mutex<Vec<Hook>>) -> Vec<Hook> {
+    std::mem::take(&mut *hooks.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Reports a hook's error and exits.
+fn report(client: &Client, path: &str, ty: &str, message: &str) -> ! {
+    if let Err(err) = crate::http::post_error(client.endpoint(), path, ty, message) {
+        panic!(
+            "failed to report SnapStart hook error: {}\ncaused by: {}",
+            err, message
+        );
+    }
+    std::process::exit(1)
+}
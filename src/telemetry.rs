@@ -0,0 +1,498 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Subscribing an extension to the [Lambda Telemetry API][telemetry].
+//!
+//! Lambda sends telemetry to an HTTP listener in the extension, in batches. A [`Subscriber`]
+//! starts one (on a thread of its own), subscribes it to the kinds of telemetry it's set up for,
+//! and hands each batch of [`Event`]s to a callback, or each event to a channel:
+//!
+//! ```rust,no_run
+//! use minlambda::extensions::{self, Extension};
+//! use minlambda::telemetry::{Record, Subscriber, Type};
+//!
+//! let extension = Extension::register("log-shipper", &[extensions::EventType::Shutdown])?;
+//! let events = Subscriber::new().types(&[Type::Function]).channel(&extension)?;
+//! std::thread::spawn(move || {
+//!     for event in events {
+//!         if let Record::Function(line) = event.record {
+//!             // ship `line` somewhere
+//!         }
+//!     }
+//! });
+//! loop {
+//!     if let extensions::Event::Shutdown { .. } = extension.next_event()? {
+//!         break;
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! An extension has to subscribe before it first asks for an event. Telemetry isn't sent while
+//! the execution environment is frozen, so what an invocation produces can arrive as late as the
+//! next event; the last of it arrives before Lambda sends the shutdown event.
+//!
+//! Environments that are still on the older [Logs API][logs] can subscribe to it instead, with
+//! [`Subscriber::logs_api`].
+//!
+//! [telemetry]: https://docs.aws.amazon.com/lambda/latest/dg/telemetry-api.html
+//! [logs]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-logs-api.html
+
+use crate::error::{Error, Result};
+use crate::extensions::Extension;
+use crate::http::{self, Headers};
+use crate::listener;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+const TELEMETRY: &str = "/2022-07-01/telemetry";
+const LOGS: &str = "/2020-08-15/logs";
+
+/// The versions of the event schema to subscribe with, for each API.
+const TELEMETRY_SCHEMA_VERSION: &str = "2022-12-13";
+const LOGS_SCHEMA_VERSION: &str = "2021-03-18";
+
+/// A kind of telemetry to subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Type {
+    /// Events from Lambda about the execution environment's lifecycle and its invocations.
+    Platform,
+    /// What the function writes to stdout and stderr.
+    Function,
+    /// What extensions write to stdout and stderr.
+    Extension,
+}
+
+impl Type {
+    fn as_str(self) -> &'static str {
+        match self {
+            Type::Platform => "platform",
+            Type::Function => "function",
+            Type::Extension => "extension",
+        }
+    }
+}
+
+/// Sets up a subscription to the Telemetry API (or the Logs API), and the listener that receives
+/// it.
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    types: Vec<Type>,
+    buffering: Option<(u32, u32, Duration)>,
+    port: u16,
+    logs_api: bool,
+}
+
+impl Default for Subscriber {
+    fn default() -> Subscriber {
+        Subscriber {
+            types: vec![Type::Platform, Type::Function, Type::Extension],
+            buffering: None,
+            port: 0,
+            logs_api: false,
+        }
+    }
+}
+
+impl Subscriber {
+    /// Creates a subscriber for all kinds of telemetry.
+    #[must_use]
+    pub fn new() -> Subscriber {
+        Subscriber::default()
+    }
+
+    /// Sets the kinds of telemetry to subscribe to.
+    #[must_use]
+    pub fn types(mut self, types: &[Type]) -> Subscriber {
+        self.types = types.to_vec();
+        self
+    }
+
+    /// Sets how Lambda batches telemetry: a batch is sent once it has `max_items` events or
+    /// `max_bytes` bytes of them, or once `timeout` has passed since its first event. Lambda's
+    /// defaults are 10,000 events, 256 KiB, and a second.
+    #[must_use]
+    pub fn buffering(mut self, max_items: u32, max_bytes: u32, timeout: Duration) -> Subscriber {
+        self.buffering = Some((max_items, max_bytes, timeout));
+        self
+    }
+
+    /// Sets the port the listener listens on. The default is to use any free port.
+    #[must_use]
+    pub fn port(mut self, port: u16) -> Subscriber {
+        self.port = port;
+        self
+    }
+
+    /// Subscribes with the Logs API rather than the Telemetry API, for environments that don't
+    /// have the Telemetry API yet.
+    ///
+    /// Events come in the Logs API's schema, which is mostly the same as the Telemetry API's.
+    /// Platform events that differ, such as `platform.report` (which has no status in the Logs
+    /// API), come through as [`Record::Platform`].
+    #[must_use]
+    pub fn logs_api(mut self) -> Subscriber {
+        self.logs_api = true;
+        self
+    }
+
+    /// Starts the listener and subscribes it for `extension`, calling `f` with each batch of
+    /// events Lambda sends.
+    ///
+    /// `f` is called on the listener's threads, one batch at a time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener can't be started or the subscription fails.
+    pub fn subscribe<F>(&self, extension: &Extension, f: F) -> Result<()>
+    where
+        F: FnMut(Vec<Event>) + Send + 'static,
+    {
+        let mut f = f;
+        let listener = TcpListener::bind(("0.0.0.0", self.port))?;
+        let port = listener.local_addr()?.port();
+        let (path, schema_version, name) = if self.logs_api {
+            (LOGS, LOGS_SCHEMA_VERSION, "minlambda-logs")
+        } else {
+            (TELEMETRY, TELEMETRY_SCHEMA_VERSION, "minlambda-telemetry")
+        };
+        let mut body = json!({
+            "schemaVersion": schema_version,
+            "types": self.types.iter().map(|ty| ty.as_str()).collect::<Vec<_>>(),
+            "destination": {
+                "protocol": "HTTP",
+                "URI": format!("http://sandbox.localdomain:{port}"),
+            },
+        });
+        if let Some((max_items, max_bytes, timeout)) = self.buffering {
+            body["buffering"] = json!({
+                "maxItems": max_items,
+                "maxBytes": max_bytes,
+                "timeoutMs": u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX),
+            });
+        }
+        let body = serde_json::to_vec(&body).map_err(Error::serialize)?;
+        let headers = Headers::new()
+            .with("lambda-extension-identifier", extension.id())
+            .with("content-type", "application/json");
+        http::api_request(extension.endpoint(), "PUT", path, &headers, &body)
+            .and_then(|(_, body)| http::drain(body))
+            .map_err(http::context("PUT", path))?;
+        // Lambda can't send anything before the subscription is made, and connections that come
+        // in before the listener's thread starts wait to be accepted.
+        listener::spawn(listener, name, move |batch| match parse_batch(batch) {
+            Some(events) => {
+                f(events);
+                true
+            }
+            None => false,
+        })?;
+        Ok(())
+    }
+
+    /// Like [`subscribe`](Subscriber::subscribe), but sends each event to the returned channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener can't be started or the subscription fails.
+    pub fn channel(&self, extension: &Extension) -> Result<Receiver<Event>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribe(extension, move |events| {
+            for event in events {
+                // Nothing's left to do with events once the receiver is gone.
+                let _ = sender.send(event);
+            }
+        })?;
+        Ok(receiver)
+    }
+}
+
+/// An event from the Telemetry API (or the Logs API).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Event {
+    /// When the event happened, as an ISO 8601 timestamp.
+    pub time: String,
+    /// What happened.
+    pub record: Record,
+}
+
+/// What a telemetry [`Event`] is about.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Record {
+    /// A line the function wrote (`function`). This is a string, or an object for functions that
+    /// log in JSON.
+    Function(Value),
+    /// A line an extension wrote (`extension`). This is a string, or an object for extensions
+    /// that log in JSON.
+    Extension(Value),
+    /// An invocation started (`platform.start`).
+    Start {
+        /// The request ID of the invocation.
+        request_id: String,
+        /// The version of the function invoked, if Lambda sent it.
+        version: Option<String>,
+    },
+    /// The runtime finished an invocation (`platform.runtimeDone`).
+    RuntimeDone {
+        /// The request ID of the invocation.
+        request_id: String,
+        /// How it went: `success`, `failure`, `error`, or `timeout`.
+        status: String,
+        /// The type of error the invocation failed with, if it did.
+        error_type: Option<String>,
+        /// How long the runtime took to respond, if Lambda sent it.
+        duration: Option<Duration>,
+        /// How many bytes the response was, if Lambda sent it.
+        produced_bytes: Option<u64>,
+    },
+    /// An invocation, including the time the extensions took after it, is over
+    /// (`platform.report`).
+    Report {
+        /// The request ID of the invocation.
+        request_id: String,
+        /// How it went: `success`, `failure`, `error`, or `timeout`.
+        status: String,
+        /// The type of error the invocation failed with, if it did.
+        error_type: Option<String>,
+        /// The invocation's metrics.
+        metrics: Metrics,
+    },
+    /// Any other platform event. `ty` is the event's type, like `platform.initStart`.
+    Platform {
+        /// The event's type.
+        ty: String,
+        /// The event's record, as Lambda sent it.
+        record: Value,
+    },
+}
+
+/// The metrics of an invocation, from a [`Record::Report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Metrics {
+    /// How long the invocation took.
+    pub duration: Duration,
+    /// How long the invocation was billed for.
+    pub billed_duration: Duration,
+    /// How much memory the function has, in megabytes.
+    pub memory_size_mb: u64,
+    /// The most memory the function has used, in megabytes.
+    pub max_memory_used_mb: u64,
+    /// How long initialization took, for the first invocation of an execution environment.
+    pub init_duration: Option<Duration>,
+    /// How long restoring the snapshot took, for the first invocation of an execution environment
+    /// restored from one.
+    pub restore_duration: Option<Duration>,
+}
+
+/// Parses a batch of events, or returns `None` if it isn't one.
+fn parse_batch(batch: &[u8]) -> Option<Vec<Event>> {
+    let batch: Vec<Value> = serde_json::from_slice(batch).ok()?;
+    Some(batch.into_iter().filter_map(parse_event).collect())
+}
+
+fn parse_event(mut value: Value) -> Option<Event> {
+    let time = String::from(value.get("time")?.as_str()?);
+    let ty = String::from(value.get("type")?.as_str()?);
+    let record = value.get_mut("record").map(Value::take).unwrap_or_default();
+    let record = match ty.as_str() {
+        "function" => Record::Function(record),
+        "extension" => Record::Extension(record),
+        _ => platform(&ty, &record).unwrap_or(Record::Platform { ty, record }),
+    };
+    Some(Event { time, record })
+}
+
+/// Parses the platform events with variants of their own, returning `None` for others (or for
+/// ones missing something their variant needs).
+fn platform(ty: &str, record: &Value) -> Option<Record> {
+    let get = |key| record.get(key).and_then(Value::as_str).map(String::from);
+    let metrics = record.get("metrics");
+    let metric = |key| metrics.and_then(|metrics| metrics.get(key));
+    let millis = |key| {
+        metric(key)
+            .and_then(Value::as_f64)
+            .and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok())
+    };
+    match ty {
+        "platform.start" => Some(Record::Start {
+            request_id: get("requestId")?,
+            version: get("version"),
+        }),
+        "platform.runtimeDone" => Some(Record::RuntimeDone {
+            request_id: get("requestId")?,
+            status: get("status")?,
+            error_type: get("errorType"),
+            duration: millis("durationMs"),
+            produced_bytes: metric("producedBytes").and_then(Value::as_u64),
+        }),
+        "platform.report" => Some(Record::Report {
+            request_id: get("requestId")?,
+            status: get("status")?,
+            error_type: get("errorType"),
+            metrics: Metrics {
+                duration: millis("durationMs")?,
+                billed_duration: millis("billedDurationMs")?,
+                memory_size_mb: metric("memorySizeMB")?.as_u64()?,
+                max_memory_used_mb: metric("maxMemoryUsedMB")?.as_u64()?,
+                init_duration: millis("initDurationMs"),
+                restore_duration: millis("restoreDurationMs"),
+            },
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_batch, Event, Metrics, Record};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    fn parse(batch: &Value) -> Vec<Event> {
+        parse_batch(&serde_json::to_vec(batch).unwrap()).unwrap()
+    }
+
+    fn record(ty: &str, record: &Value) -> Record {
+        let mut events =
+            parse(&json!([{"time": "2022-10-12T00:00:00.000Z", "type": ty, "record": record}]));
+        assert_eq!(events.len(), 1);
+        events.remove(0).record
+    }
+
+    #[test]
+    fn log_lines() {
+        assert!(matches!(
+            record("function", &json!("hello\n")),
+            Record::Function(Value::String(line)) if line == "hello\n"
+        ));
+        assert!(matches!(
+            record("extension", &json!({"level": "INFO"})),
+            Record::Extension(Value::Object(object)) if object["level"] == "INFO"
+        ));
+    }
+
+    #[test]
+    fn invocation_records() {
+        let start = record(
+            "platform.start",
+            &json!({"requestId": "r1", "version": "$LATEST"}),
+        );
+        assert!(matches!(
+            start,
+            Record::Start { request_id, version: Some(version) }
+                if request_id == "r1" && version == "$LATEST"
+        ));
+        let done = record(
+            "platform.runtimeDone",
+            &json!({
+                "requestId": "r1",
+                "status": "error",
+                "errorType": "Oops",
+                "metrics": {"durationMs": -1.0},
+            }),
+        );
+        assert!(matches!(
+            done,
+            Record::RuntimeDone {
+                status,
+                error_type: Some(error_type),
+                duration: None,
+                produced_bytes: None,
+                ..
+            } if status == "error" && error_type == "Oops"
+        ));
+        let report = record(
+            "platform.report",
+            &json!({"requestId": "r1", "status": "success", "metrics": {
+                "durationMs": 20.5,
+                "billedDurationMs": 21,
+                "memorySizeMB": 128,
+                "maxMemoryUsedMB": 30,
+                "initDurationMs": 100.0,
+            }}),
+        );
+        let Record::Report {
+            metrics,
+            error_type: None,
+            ..
+        } = report
+        else {
+            panic!("not a report: {:?}", report);
+        };
+        assert_eq!(
+            metrics,
+            Metrics {
+                duration: Duration::from_micros(20_500),
+                billed_duration: Duration::from_millis(21),
+                memory_size_mb: 128,
+                max_memory_used_mb: 30,
+                init_duration: Some(Duration::from_millis(100)),
+                restore_duration: None,
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_records() {
+        // Types without a variant of their own, and known types missing something, come through
+        // as they were sent.
+        for (ty, value) in [
+            (
+                "platform.initStart",
+                json!({"initializationType": "on-demand"}),
+            ),
+            ("platform.someFutureEvent", json!({"requestId": "r1"})),
+            ("platform.start", json!({"version": "$LATEST"})),
+            (
+                "platform.report",
+                json!({"requestId": "r1", "status": "success"}),
+            ),
+            // The Logs API's reports have no status.
+            (
+                "platform.report",
+                json!({"requestId": "r1", "metrics": {
+                    "durationMs": 1.0,
+                    "billedDurationMs": 1,
+                    "memorySizeMB": 128,
+                    "maxMemoryUsedMB": 30,
+                }}),
+            ),
+            ("someFutureType", json!("line")),
+        ] {
+            assert!(matches!(
+                record(ty, &value),
+                Record::Platform { ty: record_ty, record } if record_ty == ty && record == value
+            ));
+        }
+        assert!(matches!(
+            record("platform.someFutureEvent", &Value::Null),
+            Record::Platform {
+                record: Value::Null,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn malformed_events() {
+        let events = parse(&json!([
+            {"type": "function", "record": "no time"},
+            {"time": "2022-10-12T00:00:00.000Z", "record": "no type"},
+            {"time": 0, "type": "function", "record": "time isn't a string"},
+            "not an object",
+            {"time": "2022-10-12T00:00:00.000Z", "type": "function", "record": "ok"},
+        ]));
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0].record, Record::Function(line) if line == "ok"));
+        assert!(parse_batch(b"not json").is_none());
+        assert!(parse_batch(b"{\"time\": \"2022-10-12T00:00:00.000Z\"}").is_none());
+        assert_eq!(parse_batch(b"[]").map(|events| events.len()), Some(0));
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! HTTPS connections, with rustls and the Mozilla root certificates from `webpki-roots`.
+
+use crate::transport::Connection;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::convert::TryFrom;
+use std::io;
+use std::net::TcpStream;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+pub(crate) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+impl Connection for TlsStream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(read)?;
+        self.sock.set_write_timeout(write)
+    }
+}
+
+/// Starts a TLS session with `host` over `stream`. The handshake happens on the first read or
+/// write, so its errors are reported like any other failure of the request.
+pub(crate) fn connect(host: &str, stream: TcpStream) -> io::Result<TlsStream> {
+    let name = ServerName::try_from(host)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+        .to_owned();
+    let conn = ClientConnection::new(config()?, name).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(conn, stream))
+}
+
+/// Returns the client configuration, which is the same for every connection.
+fn config() -> io::Result<Arc<ClientConfig>> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    if let Some(config) = CONFIG.get() {
+        return Ok(Arc::clone(config));
+    }
+    // The provider is given explicitly so that another crate enabling a different one doesn't
+    // leave rustls unable to pick a default.
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::clone(CONFIG.get_or_init(|| Arc::new(config))))
+}
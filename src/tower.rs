@@ -0,0 +1,49 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::executor::{self, Executor};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_service::Service;
+
+/// [`run`](crate::run), for [`tower::Service`](tower_service::Service)s, driven by the
+/// [`Executor`] of your choice.
+///
+/// Before each event is passed to [`Service::call`], the service is polled with
+/// [`Service::poll_ready`] until it is ready. An error from `poll_ready` is reported as the
+/// invocation's error.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_tower_on<X, T, D>(executor: X, service: T) -> !
+where
+    X: Executor,
+    T: Service<D>,
+    T::Response: Serialize,
+    T::Error: crate::ErrorTypeName + 'static,
+    D: DeserializeOwned,
+{
+    let mut executor = executor;
+    let mut service = service;
+    crate::run(move |event: D| {
+        executor.block_on(std::future::poll_fn(|cx| service.poll_ready(cx)))?;
+        executor.block_on(service.call(event))
+    })
+}
+
+/// [`run`](crate::run), for [`tower::Service`](tower_service::Service)s, without an async runtime.
+///
+/// The service's futures are polled on the current thread by [`executor::BlockOn`], with the same
+/// caveats as [`run_future`](crate::run_future). Use [`run_tower_on`] for services that need a
+/// particular async runtime.
+///
+/// This function is otherwise the same as `run`: it does not return and will panic on certain
+/// unrecoverable errors.
+pub fn run_tower<T, D>(service: T) -> !
+where
+    T: Service<D>,
+    T::Response: Serialize,
+    T::Error: crate::ErrorTypeName + 'static,
+    D: DeserializeOwned,
+{
+    run_tower_on(executor::BlockOn, service)
+}
@@ -0,0 +1,307 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+//! Connecting to the runtime API over something other than TCP.
+//!
+//! minlambda speaks HTTP to the runtime API over a byte stream. Normally that's a TCP connection
+//! to the address in `AWS_LAMBDA_RUNTIME_API`, but a [`Transport`] set with
+//! [`Builder::transport`](crate::Builder::transport) or [`Client::with_transport`] can supply the
+//! streams instead: an in-memory pipe for deterministic tests, a wrapper that records traffic, or
+//! a socket type minlambda doesn't know about.
+//!
+//! ```rust,no_run
+//! use std::net::TcpStream;
+//!
+//! fn main() -> std::io::Result<()> {
+//!     minlambda::Runtime::builder()
+//!         .transport(|| {
+//!             eprintln!("connecting to the runtime API");
+//!             TcpStream::connect("127.0.0.1:9001")
+//!         })
+//!         .build()?
+//!         .run(|name: String| Ok::<_, String>(format!("Hello, {name}!")))
+//! }
+//! ```
+//!
+//! [`Client::with_transport`]: crate::Client::with_transport
+
+use crate::error::{Error, Result};
+use crate::proxy::Proxy;
+use std::fmt::{self, Debug, Display};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "vsock")]
+use vsock::VsockStream;
+
+/// Something that opens connections to the runtime API.
+///
+/// This is implemented for closures returning a [`Connection`]. Connections are opened whenever a
+/// request can't reuse an idle one, from whichever thread makes the request.
+pub trait Transport: Send + Sync {
+    /// Opens a connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection can't be opened. Errors are reported like those from
+    /// connecting over TCP, and [`Builder::connect_attempts`](crate::Builder::connect_attempts)
+    /// applies, but [`Builder::connect_timeout`](crate::Builder::connect_timeout) doesn't.
+    fn connect(&self) -> io::Result<Box<dyn Connection>>;
+}
+
+impl<F, C> Transport for F
+where
+    F: Fn() -> io::Result<C> + Send + Sync,
+    C: Connection + 'static,
+{
+    fn connect(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self()?))
+    }
+}
+
+/// A connection to the runtime API, opened by a [`Transport`].
+pub trait Connection: Read + Write + Send + Debug {
+    /// Sets the timeouts from [`Builder::read_timeout`](crate::Builder::read_timeout) and
+    /// [`Builder::write_timeout`](crate::Builder::write_timeout), right after connecting. By
+    /// default, this does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the timeouts can't be set, which fails the request.
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        let _ = (read, write);
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(read)?;
+        self.set_write_timeout(write)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(read)?;
+        self.set_write_timeout(write)
+    }
+}
+
+#[cfg(feature = "vsock")]
+impl Connection for VsockStream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(read)?;
+        self.set_write_timeout(write)
+    }
+}
+
+pub(crate) type Stream = Box<dyn Connection>;
+
+/// Socket options for TCP connections to the runtime API.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TcpOptions {
+    pub(crate) nodelay: bool,
+    /// How long a connection is idle before TCP keepalive probes are sent, if they are.
+    #[cfg(feature = "keepalive")]
+    pub(crate) keepalive: Option<Duration>,
+}
+
+impl TcpOptions {
+    fn apply(self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        #[cfg(feature = "keepalive")]
+        if let Some(time) = self.keepalive {
+            let keepalive = socket2::TcpKeepalive::new().with_time(time);
+            socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the runtime API (or another server) is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Addr {
+    Tcp(SocketAddr),
+    /// A Unix domain socket, as used by some local emulators and test harnesses.
+    #[cfg(unix)]
+    Unix(Arc<Path>),
+    /// A vsock address, for Firecracker microVMs without a TCP path to the runtime API.
+    #[cfg(feature = "vsock")]
+    Vsock {
+        cid: u32,
+        port: u32,
+    },
+    /// A server other than the runtime API, by host name.
+    Host(Host),
+    /// A user-supplied transport.
+    Custom(Custom),
+}
+
+impl Addr {
+    #[cfg(unix)]
+    pub(crate) fn unix<P: AsRef<Path>>(path: P) -> Addr {
+        Addr::Unix(Arc::from(path.as_ref()))
+    }
+
+    /// Resolves the server `name` (with any IPv6 address unbracketed) at `port`, or the proxy to
+    /// reach it through, returning at least one address.
+    pub(crate) fn resolve(name: &str, port: u16, tls: bool) -> Result<Vec<Addr>> {
+        let proxy = Proxy::from_env(name, tls)?;
+        let resolving = proxy.as_ref().map_or(name, |proxy| &proxy.addr).to_owned();
+        let resolved = match &proxy {
+            Some(proxy) => proxy.addr.to_socket_addrs(),
+            None => (name, port).to_socket_addrs(),
+        };
+        let host = Host {
+            addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+            name: Arc::from(name),
+            port,
+            tls,
+            proxy,
+        };
+        let addrs: Vec<Addr> = resolved
+            .map_err(|err| Error::Config(format!("could not resolve {resolving:?}: {err}")))?
+            .map(|addr| {
+                Addr::Host(Host {
+                    addr,
+                    ..host.clone()
+                })
+            })
+            .collect();
+        if addrs.is_empty() {
+            return Err(Error::Config(format!(
+                "{resolving:?} resolved to no addresses"
+            )));
+        }
+        Ok(addrs)
+    }
+
+    pub(crate) fn custom<T: Transport + 'static>(transport: T) -> Addr {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Addr::Custom(Custom {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            transport: Arc::new(transport),
+        })
+    }
+
+    /// Returns the proxy that requests to a plain HTTP server are sent to, with the server's URL as
+    /// their target, if there is one.
+    pub(crate) fn forwarding_proxy(&self) -> Option<&Proxy> {
+        match self {
+            Addr::Host(host) if !host.tls => host.proxy.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn connect(&self, timeout: Option<Duration>, tcp: TcpOptions) -> io::Result<Stream> {
+        match self {
+            Addr::Tcp(addr) => Ok(Box::new(connect_tcp(*addr, timeout, tcp)?)),
+            // Connecting to a Unix socket doesn't wait for the other end, so there's nothing to
+            // time out.
+            #[cfg(unix)]
+            Addr::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+            #[cfg(feature = "vsock")]
+            Addr::Vsock { cid, port } => {
+                Ok(Box::new(VsockStream::connect_with_cid_port(*cid, *port)?))
+            }
+            Addr::Host(host) => {
+                let mut stream = connect_tcp(host.addr, timeout, tcp)?;
+                // Plain HTTP requests are forwarded by the proxy instead; see `forwarding_proxy`.
+                if let Some(proxy) = host.proxy.as_ref().filter(|_| host.tls) {
+                    proxy.tunnel(&mut stream, &host.name, host.port)?;
+                }
+                #[cfg(feature = "tls")]
+                if host.tls {
+                    return Ok(Box::new(crate::tls::connect(&host.name, stream)?));
+                }
+                Ok(Box::new(stream))
+            }
+            Addr::Custom(custom) => custom.transport.connect(),
+        }
+    }
+}
+
+fn connect_tcp(
+    addr: SocketAddr,
+    timeout: Option<Duration>,
+    tcp: TcpOptions,
+) -> io::Result<TcpStream> {
+    let stream = match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&addr, timeout)?,
+        None => TcpStream::connect(addr)?,
+    };
+    tcp.apply(&stream)?;
+    Ok(stream)
+}
+
+/// Formats the address as the `Host` header of requests to it.
+impl Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Addr::Tcp(addr) => Display::fmt(addr, f),
+            #[cfg(unix)]
+            Addr::Unix(_) => f.write_str("localhost"),
+            #[cfg(feature = "vsock")]
+            Addr::Vsock { .. } => f.write_str("localhost"),
+            Addr::Host(host) => {
+                if host.name.contains(':') {
+                    write!(f, "[{}]", host.name)?;
+                } else {
+                    f.write_str(&host.name)?;
+                }
+                if host.port != if host.tls { 443 } else { 80 } {
+                    write!(f, ":{}", host.port)?;
+                }
+                Ok(())
+            }
+            Addr::Custom(_) => f.write_str("localhost"),
+        }
+    }
+}
+
+/// A server other than the runtime API, connected to over TCP at `addr`: either one of the
+/// server's addresses, or that of a proxy to reach it through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Host {
+    pub(crate) addr: SocketAddr,
+    /// The host name, for the `Host` header and for verifying the server's certificate.
+    name: Arc<str>,
+    port: u16,
+    /// Whether to connect with TLS, which is only possible with the `tls` feature.
+    tls: bool,
+    proxy: Option<Proxy>,
+}
+
+/// A user-supplied transport, with an ID to tell it apart from others (since zero-sized ones
+/// don't have distinct addresses).
+#[derive(Clone)]
+pub(crate) struct Custom {
+    id: u64,
+    transport: Arc<dyn Transport>,
+}
+
+impl PartialEq for Custom {
+    fn eq(&self, other: &Custom) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Custom {}
+
+impl Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Custom")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2020 iliana destroyer of worlds <iliana@buttslol.net>
+// SPDX-License-Identifier: MIT
+
+use crate::http::{self, Endpoint};
+use crate::Context;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+const RUNNING: u8 = 0;
+const CLAIMED_BY_HANDLER: u8 = 1;
+const CLAIMED_BY_WATCHDOG: u8 = 2;
+const CLAIMED_BY_PANIC: u8 = 3;
+
+/// Who gets to post the result of an invocation: the handler, or the watchdog or panic hook if
+/// either gets there first.
+type Claim = Arc<AtomicU8>;
+
+/// The claim on the invocation being handled, if any.
+static CURRENT: Mutex<Option<Claim>> = Mutex::new(None);
+
+/// Held by tests that begin or claim invocations, since they share the current claim.
+#[cfg(test)]
+pub(crate) static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Starts a new claim for an invocation that's just been received.
+pub(crate) fn begin() -> Claim {
+    let claim = Claim::default();
+    *CURRENT.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::clone(&claim));
+    claim
+}
+
+/// Claims the current invocation for the handler. Returns `true` if the handler should post the
+/// invocation's result, or `false` if the watchdog or panic hook already reported an error for it.
+pub(crate) fn claim() -> bool {
+    !matches!(
+        try_claim(CLAIMED_BY_HANDLER),
+        Err(CLAIMED_BY_WATCHDOG | CLAIMED_BY_PANIC)
+    )
+}
+
+/// Claims the current invocation for the panic hook. Returns `true` if nothing has posted or
+/// started posting the invocation's result yet.
+pub(crate) fn claim_for_panic() -> bool {
+    matches!(try_claim(CLAIMED_BY_PANIC), Ok(()) | Err(CLAIMED_BY_PANIC))
+}
+
+fn try_claim(by: u8) -> Result<(), u8> {
+    match &*CURRENT.lock().unwrap_or_else(PoisonError::into_inner) {
+        Some(claim) => claim
+            .compare_exchange(RUNNING, by, Ordering::AcqRel, Ordering::Acquire)
+            .map(drop),
+        None => Ok(()),
+    }
+}
+
+/// A thread that reports a timeout error for invocations still running shortly before their
+/// deadline.
+pub(crate) struct Watchdog {
+    sender: Sender<(Instant, String, Claim)>,
+    margin: Duration,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn(endpoint: Endpoint, margin: Duration) -> Watchdog {
+        let (sender, receiver) = mpsc::channel::<(Instant, String, Claim)>();
+        std::thread::spawn(move || {
+            let message = format!(
+                "handler still running {}ms before the invocation deadline",
+                margin.as_millis()
+            );
+            // Only the latest invocation matters, so a new one replaces any pending one.
+            let mut pending: Option<(Instant, String, Claim)> = None;
+            loop {
+                let next = match &pending {
+                    Some((at, _, _)) => {
+                        receiver.recv_timeout(at.saturating_duration_since(Instant::now()))
+                    }
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match next {
+                    Ok(watch) => pending = Some(watch),
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Some((_, request_id, claim)) = pending.take() {
+                            if claim
+                                .compare_exchange(
+                                    RUNNING,
+                                    CLAIMED_BY_WATCHDOG,
+                                    Ordering::AcqRel,
+                                    Ordering::Acquire,
+                                )
+                                .is_ok()
+                            {
+                                // There's no one to tell if this fails; Lambda will report the
+                                // timeout itself.
+                                let _ = http::post_error(
+                                    &endpoint,
+                                    &format!("invocation/{request_id}/error"),
+                                    "TaskTimedOut",
+                                    &message,
+                                );
+                            }
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        Watchdog { sender, margin }
+    }
+
+    /// Starts watching an invocation.
+    pub(crate) fn start(&self, context: &Context, claim: Claim) {
+        let at = context
+            .deadline
+            .to_instant()
+            .checked_sub(self.margin)
+            .unwrap_or_else(Instant::now);
+        let _ = self.sender.send((at, context.request_id.clone(), claim));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        begin, claim, claim_for_panic, Claim, Watchdog, CLAIMED_BY_HANDLER, CLAIMED_BY_WATCHDOG,
+        TEST_LOCK,
+    };
+    use crate::context::Deadline;
+    use crate::http::Endpoint;
+    use crate::transport::Addr;
+    use crate::Context;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::Ordering;
+    use std::sync::PoisonError;
+    use std::time::Duration;
+
+    #[test]
+    fn claims() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        begin();
+        assert!(claim_for_panic());
+        assert!(claim_for_panic());
+        assert!(!claim());
+
+        let current = begin();
+        assert!(claim());
+        assert!(!claim_for_panic());
+        assert!(claim());
+
+        begin().store(CLAIMED_BY_WATCHDOG, Ordering::Release);
+        assert!(!claim());
+        assert!(!claim_for_panic());
+        assert_eq!(current.load(Ordering::Acquire), CLAIMED_BY_HANDLER);
+    }
+
+    #[test]
+    fn reports_timeouts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut endpoint = Endpoint::new(Addr::Tcp(listener.local_addr().unwrap()));
+        endpoint.keep_alive = false;
+        let watchdog = Watchdog::spawn(endpoint, Duration::from_secs(1));
+        let context = |request_id: &str| Context {
+            request_id: request_id.to_owned(),
+            deadline: Deadline::from_unix_millis(0),
+            invoked_function_arn: String::new(),
+            trace_id: None,
+            client_context: None,
+            identity: None,
+        };
+
+        // An invocation the handler has already claimed isn't reported.
+        let handled = Claim::default();
+        handled.store(CLAIMED_BY_HANDLER, Ordering::Release);
+        watchdog.start(&context("r1"), handled);
+        std::thread::sleep(Duration::from_millis(50));
+        let claim = Claim::default();
+        watchdog.start(&context("r2"), Claim::clone(&claim));
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut request_line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut request_line)
+            .unwrap();
+        assert_eq!(
+            request_line,
+            "POST /2018-06-01/runtime/invocation/r2/error HTTP/1.1\r\n"
+        );
+        (&stream)
+            .write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert_eq!(claim.load(Ordering::Acquire), CLAIMED_BY_WATCHDOG);
+    }
+}